@@ -16,10 +16,21 @@ export NAME="config-service"
 
 # config-data
 
+# the metrics listener has no auth, so its port is only published when asked for, e.g.
+# METRICS_PORT=22201 ./run.sh, with metrics_address set in the server config
+METRICS=""
+if [ -n "$METRICS_PORT" ]
+then
+    METRICS="--publish $METRICS_PORT:$METRICS_PORT/tcp"
+fi
+
 docker run -it -d -u dpw \
     --network=service-net  \
     --publish 22200:22200/udp  \
+    $METRICS \
     --hostname=config \
+    --env UDP_SERVICE_HOST=0.0.0.0 \
+    --env UDP_SERVICE_PORT=22200 \
     --name $NAME  \
     --mount source=config-data,target=/home/dpw/data \
     "darrylwest/$NAME:latest"
@@ -0,0 +1,206 @@
+/// the access log; one line per request written to its own rolling file, apart from log4rs
+///
+/// each line is: time client command key code bytes-in bytes-out, with a trailing `audit` on
+/// the commands that change data when audit is on, e.g.
+/// 2026-01-05T18:21:03.114Z 127.0.0.1:53211 set k1 200 12 8 audit
+use crate::config::AccessLogConfig;
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// the default size in bytes a file reaches before it is rolled
+pub const DEFAULT_MAX_SIZE: u64 = 1_000_000;
+
+/// the default number of rolled files kept
+pub const DEFAULT_KEEP: usize = 5;
+
+/// the fields of an access log line
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccessEntry {
+    pub client: Option<SocketAddr>,
+    pub command: String,
+    pub key: Option<String>,
+    pub code: u16,
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub mutating: bool,
+}
+
+impl AccessEntry {
+    /// the line without the time
+    fn as_string(&self, audit: bool) -> String {
+        let mut line = format!(
+            "{} {} {} {} {} {}",
+            self.client
+                .map_or("local".to_string(), |addr| addr.to_string()),
+            self.command,
+            self.key.as_deref().unwrap_or("-"),
+            self.code,
+            self.bytes_in,
+            self.bytes_out
+        );
+        if audit && self.mutating {
+            line.push_str(" audit");
+        }
+
+        line
+    }
+}
+
+#[derive(Debug)]
+pub struct AccessLog {
+    path: String,
+    max_size: u64,
+    keep: usize,
+    audit: bool,
+    file: File,
+    size: u64,
+}
+
+impl AccessLog {
+    /// open the log from config, creating its folder and appending to an existing file
+    pub fn create(config: &AccessLogConfig) -> Result<AccessLog> {
+        if let Some(folder) = Path::new(&config.path).parent() {
+            fs::create_dir_all(folder)?;
+        }
+
+        let file = open(&config.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(AccessLog {
+            path: config.path.to_string(),
+            max_size: config.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+            keep: config.keep.unwrap_or(DEFAULT_KEEP),
+            audit: config.audit.unwrap_or(false),
+            file,
+            size,
+        })
+    }
+
+    /// write the entry, rolling the file first if it would grow past the max size
+    pub fn record(&mut self, entry: &AccessEntry) -> Result<()> {
+        let line = format!(
+            "{} {}\n",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            entry.as_string(self.audit)
+        );
+
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.roll()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// the path of a rolled file, e.g. logs/access.log.1
+    fn rolled(&self, n: usize) -> String {
+        format!("{}.{}", self.path, n)
+    }
+
+    /// shift the rolled files up by one, dropping the oldest, and start a new file
+    fn roll(&mut self) -> Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rolled(self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(self.rolled(n), self.rolled(n + 1));
+            }
+            fs::rename(&self.path, self.rolled(1))?;
+        }
+
+        self.file = open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+/// open the file to append
+fn open(path: &str) -> Result<File> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_log(folder: &str, max_size: u64, audit: bool) -> AccessLog {
+        let _ = fs::remove_dir_all(folder);
+        AccessLog::create(&AccessLogConfig {
+            path: format!("{}/access.log", folder),
+            max_size: Some(max_size),
+            keep: Some(2),
+            audit: Some(audit),
+        })
+        .unwrap()
+    }
+
+    fn create_entry(command: &str, mutating: bool) -> AccessEntry {
+        AccessEntry {
+            client: Some(SocketAddr::from(([127, 0, 0, 1], 9001))),
+            command: command.to_string(),
+            key: Some("k1".to_string()),
+            code: 200,
+            bytes_in: 12,
+            bytes_out: 8,
+            mutating,
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let folder = "tests/access-lines-out";
+        let mut log = create_log(folder, DEFAULT_MAX_SIZE, true);
+        log.record(&create_entry("set", true)).unwrap();
+        log.record(&create_entry("get", false)).unwrap();
+        log.record(&AccessEntry::default()).unwrap();
+
+        let text = fs::read_to_string(format!("{}/access.log", folder)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("Z 127.0.0.1:9001 set k1 200 12 8 audit"));
+        assert!(lines[1].ends_with(" 127.0.0.1:9001 get k1 200 12 8"));
+        assert!(lines[2].ends_with(" local  - 0 0 0"));
+
+        let entry = create_entry("set", true);
+        assert!(!entry.as_string(false).ends_with("audit"));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn rolls_and_keeps() {
+        let folder = "tests/access-roll-out";
+        let mut log = create_log(folder, 120, false);
+        for _ in 0..8 {
+            log.record(&create_entry("get", false)).unwrap();
+        }
+
+        // each line is 56 bytes, so two fit in a file
+        let path = format!("{}/access.log", folder);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        assert!(Path::new(&format!("{}.1", path)).exists());
+        assert!(Path::new(&format!("{}.2", path)).exists());
+        assert!(!Path::new(&format!("{}.3", path)).exists());
+
+        // reopening appends to the current file
+        let mut log = AccessLog::create(&AccessLogConfig {
+            path: path.to_string(),
+            max_size: Some(1000),
+            keep: None,
+            audit: None,
+        })
+        .unwrap();
+        log.record(&create_entry("get", false)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -19,16 +19,25 @@ struct Cli {
     /// config filename to override default
     #[arg(short, long, default_value_t = String::from(".config/udp-config/client-config.toml"))]
     config_file: String,
+
+    /// subscribe to the channel and print the pushed messages; may be repeated
+    #[arg(short, long)]
+    listen: Vec<String>,
+
+    /// print every request the server handles; uses the first admin key from config
+    #[arg(short, long)]
+    monitor: bool,
 }
 
-/// create the repl client from args return the client or error
-fn create_client(args: Vec<String>) -> Result<Client> {
+/// create the repl client from args return the client, channels to listen to and monitor flag,
+/// or error
+fn create_client(args: Vec<String>) -> Result<(Client, Vec<String>, bool)> {
     let cli = Cli::parse_from(args);
 
     match Config::read_config(&cli.config_file) {
         Ok(config) => {
             let _ = config.start_logger();
-            Ok(Client::new(config))
+            Ok((Client::new(config), cli.listen, cli.monitor))
         }
         Err(e) => Err(anyhow!("could not read config: {}", e)),
     }
@@ -39,7 +48,14 @@ fn main() -> Result<()> {
     env::set_current_dir(home).unwrap();
 
     let args: Vec<String> = env::args().collect();
-    create_client(args)?.start()
+    let (client, channels, monitor) = create_client(args)?;
+    if monitor {
+        client.monitor()
+    } else if channels.is_empty() {
+        client.start()
+    } else {
+        client.listen(&channels)
+    }
 }
 
 #[cfg(test)]
@@ -54,6 +70,33 @@ mod tests {
         assert!(true);
     }
 
+    #[test]
+    fn create_listen_client() {
+        let args: Vec<String> = vec![
+            "udp-client".to_string(),
+            "--config-file".to_string(),
+            "tests/server-config.toml".to_string(),
+            "--listen".to_string(),
+            "config".to_string(),
+        ];
+        let (_, channels, monitor) = create_client(args).unwrap();
+        assert_eq!(channels, vec!["config".to_string()]);
+        assert!(!monitor);
+    }
+
+    #[test]
+    fn create_monitor_client() {
+        let args: Vec<String> = vec![
+            "udp-client".to_string(),
+            "--config-file".to_string(),
+            "tests/server-config.toml".to_string(),
+            "--monitor".to_string(),
+        ];
+        let (_, channels, monitor) = create_client(args).unwrap();
+        assert!(channels.is_empty());
+        assert!(monitor);
+    }
+
     #[test]
     fn create_client_bad() {
         let args: Vec<String> = vec![
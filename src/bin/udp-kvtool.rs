@@ -0,0 +1,323 @@
+///
+/// offline tool for .kv snapshot files
+///
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use tiny_kv::db::DataStore;
+
+#[derive(Debug, Parser)]
+#[command(
+    name="udp-kvtool",
+    author,
+    version,
+    about="An offline tool for udp-server .kv data and snapshot files.",
+    long_about=None,
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// load the file and report any problems
+    Validate { filename: String },
+    /// show the number of elements
+    Count { filename: String },
+    /// show the keys added (+), removed (-) and changed (~) from first to second
+    Diff { first: String, second: String },
+    /// merge the files into output; later files win on duplicate keys
+    Merge {
+        #[arg(short, long)]
+        output: String,
+        #[arg(required = true)]
+        filenames: Vec<String>,
+    },
+    /// write the elements that match the key prefix to output
+    Filter {
+        #[arg(short, long)]
+        prefix: String,
+        #[arg(short, long)]
+        output: String,
+        filename: String,
+    },
+    /// convert between .kv and .json using the file extensions
+    Convert { input: String, output: String },
+    /// dump the file as json to stdout
+    Dump { filename: String },
+}
+
+/// true if the filename should be read or written as json
+fn is_json(filename: &str) -> bool {
+    filename.ends_with(".json")
+}
+
+/// load the file into a data store; .kv files use the same loader as the server
+fn load(filename: &str) -> Result<DataStore> {
+    let db = DataStore::create();
+    if is_json(filename) {
+        let text = fs::read_to_string(filename)?;
+        let map: BTreeMap<String, String> = serde_json::from_str(&text)?;
+        for (key, value) in map {
+            db.set(&key, value.into_bytes());
+        }
+    } else {
+        db.loaddb(filename)
+            .map_err(|e| anyhow!("could not load {}: {}", filename, e))?;
+    }
+
+    Ok(db)
+}
+
+/// return the data as a sorted map of the values as stored
+fn entries(db: &DataStore) -> BTreeMap<String, Vec<u8>> {
+    let mut map = BTreeMap::new();
+    for key in db.keys() {
+        if let Some(value) = db.get(&key) {
+            map.insert(key, value);
+        }
+    }
+
+    map
+}
+
+/// return the data as a sorted map of strings; an error if a value is not utf8
+fn as_text(db: &DataStore) -> Result<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    for (key, value) in entries(db) {
+        let text = String::from_utf8(value)
+            .map_err(|_| anyhow!("key {} has a value that is not utf8", key))?;
+        map.insert(key, text);
+    }
+
+    Ok(map)
+}
+
+/// return the data as a sorted map of strings to show; values that are not utf8 are decoded
+/// lossily
+fn as_display(db: &DataStore) -> BTreeMap<String, String> {
+    entries(db)
+        .into_iter()
+        .map(|(key, value)| (key, String::from_utf8_lossy(&value).to_string()))
+        .collect()
+}
+
+/// copy the elements with keys that start with prefix
+fn copy_entries(from: &DataStore, to: &DataStore, prefix: &str) {
+    for (key, value) in entries(from) {
+        if key.starts_with(prefix) {
+            to.set(&key, value);
+        }
+    }
+}
+
+/// write the data store to the file as .kv or json
+fn save(db: &DataStore, filename: &str) -> Result<usize> {
+    if is_json(filename) {
+        let map = as_text(db)?;
+        fs::write(filename, serde_json::to_string_pretty(&map)?)?;
+        Ok(map.len())
+    } else {
+        db.savedb(filename)
+            .map_err(|e| anyhow!("could not save {}: {}", filename, e))
+    }
+}
+
+/// report the element count, lines skipped by the loader and values that are not utf8
+fn validate(filename: &str) -> Result<String> {
+    let db = load(filename)?;
+    let mut problems = Vec::new();
+
+    if !is_json(filename) {
+        let text = fs::read_to_string(filename)?;
+        let lines = text.lines().filter(|l| !l.trim().is_empty()).count();
+        if lines != db.dbsize() {
+            problems.push(format!(
+                "{} lines but {} elements; duplicate keys or malformed lines",
+                lines,
+                db.dbsize()
+            ));
+        }
+    }
+
+    for key in db.keys() {
+        if let Some(value) = db.get(&key) {
+            if String::from_utf8(value).is_err() {
+                problems.push(format!("key {} has a value that is not utf8", key));
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(format!("{}: ok, {} elements", filename, db.dbsize()))
+    } else {
+        Err(anyhow!("{}: {}", filename, problems.join("\n")))
+    }
+}
+
+/// compare the two files and return the differences sorted by key
+fn diff(first: &str, second: &str) -> Result<Vec<String>> {
+    let a = entries(&load(first)?);
+    let b = entries(&load(second)?);
+
+    let mut lines = Vec::new();
+    for (key, value) in a.iter() {
+        match b.get(key) {
+            None => lines.push(format!("- {}", key)),
+            Some(v) if v != value => {
+                lines.push(format!("~ {} {}", key, String::from_utf8_lossy(v)))
+            }
+            _ => (),
+        }
+    }
+
+    for (key, value) in b.iter() {
+        if !a.contains_key(key) {
+            lines.push(format!("+ {} {}", key, String::from_utf8_lossy(value)));
+        }
+    }
+
+    lines.sort_by(|x, y| x[2..].cmp(&y[2..]));
+
+    Ok(lines)
+}
+
+/// merge the files into a single data store
+fn merge(filenames: &[String]) -> Result<DataStore> {
+    let db = DataStore::create();
+    for filename in filenames {
+        copy_entries(&load(filename)?, &db, "");
+    }
+
+    Ok(db)
+}
+
+/// return a data store with just the keys that start with prefix
+fn filter(filename: &str, prefix: &str) -> Result<DataStore> {
+    let db = DataStore::create();
+    copy_entries(&load(filename)?, &db, prefix);
+
+    Ok(db)
+}
+
+/// run the command and return the text to show
+fn run(args: Vec<String>) -> Result<String> {
+    let cli = Cli::parse_from(args);
+
+    match cli.command {
+        Command::Validate { filename } => validate(&filename),
+        Command::Count { filename } => Ok(load(&filename)?.dbsize().to_string()),
+        Command::Diff { first, second } => Ok(diff(&first, &second)?.join("\n")),
+        Command::Merge { output, filenames } => {
+            let sz = save(&merge(&filenames)?, &output)?;
+            Ok(format!("merged {} elements to {}", sz, output))
+        }
+        Command::Filter {
+            prefix,
+            output,
+            filename,
+        } => {
+            let sz = save(&filter(&filename, &prefix)?, &output)?;
+            Ok(format!("wrote {} elements to {}", sz, output))
+        }
+        Command::Convert { input, output } => {
+            let sz = save(&load(&input)?, &output)?;
+            Ok(format!("converted {} elements to {}", sz, output))
+        }
+        Command::Dump { filename } => Ok(serde_json::to_string_pretty(&as_display(&load(
+            &filename,
+        )?))?),
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let text = run(args)?;
+    println!("{}", text);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USERS: &str = "tests/users-ref.kv";
+
+    fn args(list: &[&str]) -> Vec<String> {
+        let mut args = vec!["udp-kvtool".to_string()];
+        args.extend(list.iter().map(|s| s.to_string()));
+        args
+    }
+
+    #[test]
+    fn count_matches_loaddb() {
+        let db = DataStore::create();
+        let sz = db.loaddb(USERS).unwrap();
+        let text = run(args(&["count", USERS])).unwrap();
+        assert_eq!(text, sz.to_string());
+    }
+
+    #[test]
+    fn validate_ok_and_bad() {
+        let text = run(args(&["validate", USERS])).unwrap();
+        assert!(text.contains("ok"));
+        assert!(run(args(&["validate", "/bad/file.kv"])).is_err());
+    }
+
+    #[test]
+    fn convert_round_trip() {
+        let json = "tests/kvtool-convert-out.json";
+        let kv = "tests/kvtool-convert-out.kv";
+        run(args(&["convert", USERS, json])).unwrap();
+        run(args(&["convert", json, kv])).unwrap();
+
+        let lines = diff(USERS, kv).unwrap();
+        assert!(lines.is_empty());
+        let _ = fs::remove_file(json);
+    }
+
+    #[test]
+    fn filter_merge_diff() {
+        let out = "tests/kvtool-filter-out.kv";
+        run(args(&["filter", "--prefix", "u10", "--output", out, USERS])).unwrap();
+        let filtered = load(out).unwrap();
+        assert!(filtered.keys().iter().all(|k| k.starts_with("u10")));
+
+        let lines = diff(USERS, out).unwrap();
+        assert!(lines.iter().all(|l| l.starts_with("- ")));
+
+        let merged = "tests/kvtool-merge-out.kv";
+        run(args(&["merge", "--output", merged, out, USERS])).unwrap();
+        assert!(diff(USERS, merged).unwrap().is_empty());
+    }
+
+    #[test]
+    fn values_kept_as_bytes() {
+        let value = vec![b'a', 0xff, 0xfe, b'z'];
+        let from = DataStore::create();
+        from.set("u101", value.clone());
+        from.set("x200", b"other".to_vec());
+
+        let to = DataStore::create();
+        copy_entries(&from, &to, "u1");
+        assert_eq!(to.get("u101"), Some(value));
+        assert_eq!(to.dbsize(), 1);
+
+        // json can't hold the value, so it is an error rather than a lossy copy
+        let json = "tests/kvtool-bytes-out.json";
+        assert!(save(&from, json).is_err());
+        assert!(!std::path::Path::new(json).exists());
+        assert_eq!(as_display(&from)["u101"], "a\u{fffd}\u{fffd}z");
+    }
+
+    #[test]
+    fn dump_json() {
+        let text = run(args(&["dump", USERS])).unwrap();
+        let map: BTreeMap<String, String> = serde_json::from_str(&text).unwrap();
+        assert_eq!(map.len(), load(USERS).unwrap().dbsize());
+    }
+}
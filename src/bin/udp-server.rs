@@ -1,12 +1,12 @@
 //
 // the main driver
 //
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
-use log::{error, info};
+use log::{error, info, warn};
 use std::env;
 use tiny_kv::db::DataStore;
-use udp_socket_service::config::Config;
+use udp_socket_service::config::{Config, Overrides};
 use udp_socket_service::handler::Handler;
 use udp_socket_service::server::Server;
 
@@ -26,10 +26,42 @@ struct Cli {
     /// an optional data file to load on startup.  this overrides the file in config
     #[arg(short, long)]
     data_file: Option<String>,
+
+    /// the host to listen on; overrides UDP_SERVICE_HOST and the config
+    #[arg(long)]
+    host: Option<String>,
+
+    /// the port to listen on; overrides UDP_SERVICE_PORT and the config
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// the log4rs file; overrides UDP_SERVICE_LOGGING_CONFIG and the config
+    #[arg(short, long)]
+    logging_config: Option<String>,
+
+    /// print the config merged from the file, environment and command line, then exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// check the merged config and print the problems found, then exit; non-zero if any
+    #[arg(long)]
+    check_config: bool,
+}
+
+impl Cli {
+    /// the settings given on the command line
+    fn overrides(&self) -> Overrides {
+        Overrides {
+            host: self.host.clone(),
+            port: self.port,
+            data_file: self.data_file.clone(),
+            logging_config: self.logging_config.clone(),
+        }
+    }
 }
 
 /// create the default handler
-fn create_handler(datafile: Option<String>) -> Handler {
+fn create_handler(config: &Config, datafile: Option<String>) -> Handler {
     let db = DataStore::create();
     if datafile.is_some() {
         let filename = datafile.unwrap();
@@ -43,27 +75,38 @@ fn create_handler(datafile: Option<String>) -> Handler {
         }
     }
 
-    Handler::new(db)
+    Handler::create(db, config)
+}
+
+/// print the problems with the config; an error if there are any
+fn check_config(config: &Config) -> Result<()> {
+    let problems = config.validate();
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+
+    if problems.is_empty() {
+        println!("config ok");
+        Ok(())
+    } else {
+        Err(anyhow!("{} config problems found", problems.len()))
+    }
 }
 
 /// create the udp server
-fn create_server(args: Vec<String>) -> Result<Server> {
-    let cli = Cli::parse_from(args);
-    let config = Config::read_config(&cli.config_file).unwrap();
+fn create_server(cli: Cli) -> Result<Server> {
+    let config = Config::load(&cli.config_file, &cli.overrides())?;
     Config::write_pid_file();
 
     let _ = config.start_logger();
 
     info!("cli: {:?}", cli);
-
-    let mut datafile: Option<String> = None;
-    if cli.data_file.is_some() {
-        datafile = cli.data_file;
-    } else if config.data_file.is_some() {
-        datafile = config.data_file.clone();
+    for problem in config.validate() {
+        warn!("config: {}", problem);
     }
 
-    let handler = create_handler(datafile);
+    let datafile = config.data_file.clone();
+    let handler = create_handler(&config, datafile);
     let server = Server::create(config.clone(), handler);
 
     Ok(server)
@@ -72,7 +115,17 @@ fn create_server(args: Vec<String>) -> Result<Server> {
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
-    create_server(args)?.start().await
+    let cli = Cli::parse_from(args);
+    if cli.print_config {
+        let config = Config::load(&cli.config_file, &cli.overrides())?;
+        print!("{}", config.to_redacted_toml()?);
+        return Ok(());
+    }
+    if cli.check_config {
+        return check_config(&Config::load(&cli.config_file, &cli.overrides())?);
+    }
+
+    create_server(cli)?.start().await
 }
 
 #[cfg(test)]
@@ -83,14 +136,14 @@ mod tests {
     fn test_create_handler() {
         // let args: Vec<String> = vec!["udp-server".to_string()];
         let filename = "./tests/users-ref.kv".to_string();
-        let handler = create_handler(Some(filename));
+        let handler = create_handler(&Config::default(), Some(filename));
 
         assert!(handler.dbsize() >= 10);
     }
 
     #[test]
     fn create_bad_handler() {
-        let handler = create_handler(Some("/not/a/real/file".to_string()));
+        let handler = create_handler(&Config::default(), Some("/not/a/real/file".to_string()));
         println!("{:?}", handler);
     }
 
@@ -98,7 +151,7 @@ mod tests {
     fn create_test_server() {
         // let handler = create_handler(None);
         let args: Vec<String> = vec![];
-        let server = create_server(args);
+        let server = create_server(Cli::parse_from(args));
         println!("{:?}", server);
     }
 
@@ -110,7 +163,46 @@ mod tests {
             "--data-file".to_string(),
             "/bad/file".to_string(),
         ];
-        let server = create_server(args);
+        let server = create_server(Cli::parse_from(args));
         println!("{:?}", server);
     }
+
+    #[test]
+    fn cli_overrides() {
+        let args: Vec<String> = vec![
+            "runner".to_string(),
+            "--port".to_string(),
+            "22300".to_string(),
+            "--logging-config".to_string(),
+            "config/rolling.yaml".to_string(),
+            "--print-config".to_string(),
+        ];
+        let cli = Cli::parse_from(args);
+        assert!(cli.print_config);
+
+        let config = Config::load(&cli.config_file, &cli.overrides()).unwrap();
+        assert_eq!(config.port, 22300);
+        assert_eq!(config.logging_config, "config/rolling.yaml");
+        assert_eq!(config.data_file, Some("data/users.kv".to_string()));
+    }
+
+    #[test]
+    fn check_configs() {
+        let config = Config::read_config("config/server-config.toml").unwrap();
+        assert!(check_config(&config).is_ok());
+
+        let config = Config::read_config("tests/bad-config.toml").unwrap();
+        let resp = check_config(&config);
+        assert_eq!(resp.unwrap_err().to_string(), "6 config problems found");
+    }
+
+    #[test]
+    fn create_bad_server() {
+        let args: Vec<String> = vec![
+            "runner".to_string(),
+            "--config-file".to_string(),
+            "/not/a/config.toml".to_string(),
+        ];
+        assert!(create_server(Cli::parse_from(args)).is_err());
+    }
 }
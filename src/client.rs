@@ -1,7 +1,8 @@
 use crate::config::Config;
 use anyhow::Result;
-use std::io::{self, Write};
+use std::io::{self, ErrorKind, Write};
 use std::net::UdpSocket;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub struct Client {
@@ -21,12 +22,74 @@ fn help(startup: bool) -> String {
         buf.push_str(" del key -> ok\n");
         buf.push_str(" keys -> [list]\n");
         buf.push_str(" dbsize -> [list]\n");
-        buf.push_str(" loaddb [filename] -> size\n");
+        buf.push_str(" loaddb [--replace] [filename] -> size; --replace is admin\n");
         buf.push_str(" savedb [filename] -> size\n");
+        buf.push_str(" listsnapshots -> [version:timestamp]\n");
+        buf.push_str(" restore version -> size (admin)\n");
+        buf.push_str(" select db -> db\n");
+        buf.push_str(" databases -> [db:size]\n");
+        buf.push_str(" exists key [key...] -> count\n");
+        buf.push_str(" rename old new -> 1\n");
+        buf.push_str(" renamenx old new -> 1 or 0\n");
+        buf.push_str(" copy src dst -> 1 or 0\n");
+        buf.push_str(" type key -> string, hash, list, set, zset or none\n");
+        buf.push_str(" hset key field value -> 1 new or 0 updated\n");
+        buf.push_str(" hget key field -> value\n");
+        buf.push_str(" hdel key field [field...] -> count\n");
+        buf.push_str(" hgetall key [start] -> {json}\n");
+        buf.push_str(" hkeys key -> [fields]\n");
+        buf.push_str(" hlen key -> count\n");
+        buf.push_str(" lpush key value [value...] -> length\n");
+        buf.push_str(" rpush key value [value...] -> length\n");
+        buf.push_str(" lpop key -> value\n");
+        buf.push_str(" rpop key -> value\n");
+        buf.push_str(" lrange key start stop -> [values]\n");
+        buf.push_str(" llen key -> length\n");
+        buf.push_str(" ltrim key start stop -> ok\n");
+        buf.push_str(" sadd key member [member...] -> count added\n");
+        buf.push_str(" srem key member [member...] -> count removed\n");
+        buf.push_str(" smembers key [start] -> [members]\n");
+        buf.push_str(" sismember key member -> 1 or 0\n");
+        buf.push_str(" scard key -> count\n");
+        buf.push_str(" zadd key score member [score member...] -> count added\n");
+        buf.push_str(" zrange key start stop -> [members by score]\n");
+        buf.push_str(" zrank key member -> rank\n");
+        buf.push_str(" zscore key member -> score\n");
+        buf.push_str(" zincrby key incr member -> score\n");
+        buf.push_str(" subscribe channel -> lease seconds\n");
+        buf.push_str(" unsubscribe channel -> 1 or 0\n");
+        buf.push_str(" publish channel message -> count\n");
+        buf.push_str(" watch key-or-prefix* -> lease seconds\n");
+        buf.push_str(" unwatch key-or-prefix* -> 1 or 0\n");
+        buf.push_str(" watchers -> [db:pattern:addr:seconds] (admin)\n");
+        buf.push_str(" version key -> version\n");
+        buf.push_str(" getmeta key -> {version,modified,type,size,history}\n");
+        buf.push_str(" history key [n [start]] -> [{version,modified,value}]\n");
+        buf.push_str(" revert key version -> version\n");
+        buf.push_str(" exec [[\"cmd\",\"arg\"...]...] -> [responses]\n");
+        buf.push_str(" eval (if (== (get \"a\") \"on\") (set \"b\" \"yes\")) -> value\n");
+        buf.push_str(" auth key -> ok\n");
+        buf.push_str(" flushdb -> size (admin)\n");
         buf.push_str(" ping -> PONG\n");
         buf.push_str(" now -> unix ts\n");
         buf.push_str(" now_ns -> nano-seconds\n");
         buf.push_str(" status -> uptime\n");
+        buf.push_str(" info [section] -> {section:{name:value}}\n");
+        buf.push_str(
+            " stats [command] -> {command:{calls,errors,not_found,p50_us,p95_us,p99_us}}\n",
+        );
+        buf.push_str(" stats reset -> ok (admin)\n");
+        buf.push_str(
+            " slowlog get [n] -> [{id,timestamp,client,command,params,duration_us}] (admin)\n",
+        );
+        buf.push_str(" slowlog len -> count\n");
+        buf.push_str(" slowlog reset -> ok (admin)\n");
+        buf.push_str(" monitor -> lease seconds (admin)\n");
+        buf.push_str(" unmonitor -> 1 or 0\n");
+        buf.push_str(" config reload -> the reloaded and restart settings (admin)\n");
+        buf.push_str(" config get name -> the value of the setting (admin)\n");
+        buf.push_str(" config set name value -> ok (admin)\n");
+        buf.push_str(" config rewrite -> the config file written (admin)\n");
     }
 
     buf
@@ -107,6 +170,89 @@ impl Client {
     pub fn start(&self) -> Result<()> {
         self.start_repl(self.create_socket()?, self.create_server_addr().as_str())
     }
+
+    /// subscribe to the channels and print the pushed messages until stopped
+    pub fn listen(&self, channels: &[String]) -> Result<()> {
+        let socket = self.create_socket()?;
+        let server_address = self.create_server_addr();
+        let requests: Vec<String> = channels
+            .iter()
+            .map(|c| format!("subscribe {}", c))
+            .collect();
+        self.listen_loop(socket, server_address.as_str(), &requests, None)?;
+
+        Ok(())
+    }
+
+    /// auth with the first admin key from config, then print every request the server handles
+    pub fn monitor(&self) -> Result<()> {
+        let socket = self.create_socket()?;
+        let server_address = self.create_server_addr();
+        let mut requests: Vec<String> = self
+            .ctx
+            .admin_keys
+            .iter()
+            .flatten()
+            .take(1)
+            .map(|key| format!("auth {}", key))
+            .collect();
+        requests.push("monitor".to_string());
+        self.listen_loop(socket, server_address.as_str(), &requests, None)?;
+
+        Ok(())
+    }
+
+    /// send the requests again at half the lease and print the pushed messages; returns the
+    /// message count
+    fn listen_loop(
+        &self,
+        socket: UdpSocket,
+        server_address: &str,
+        requests: &[String],
+        limit: Option<usize>,
+    ) -> Result<usize> {
+        let mut renew_at = Instant::now();
+        let mut count = 0;
+        loop {
+            if Instant::now() >= renew_at {
+                for request in requests {
+                    socket.send_to(request.as_bytes(), server_address)?;
+                }
+                // until the reply with the lease arrives
+                renew_at = Instant::now() + Duration::from_secs(5);
+            }
+
+            let mut buffer = [0; 1024];
+            match socket.recv_from(&mut buffer) {
+                Ok((amt, _)) => {
+                    let text = String::from_utf8_lossy(&buffer[..amt]).to_string();
+                    if ["message:", "notify:", "monitor:"]
+                        .iter()
+                        .any(|prefix| text.starts_with(prefix))
+                    {
+                        println!("{}", text);
+                        count += 1;
+                        if limit.is_some_and(|limit| count >= limit) {
+                            return Ok(count);
+                        }
+                    } else if let Some(lease) = parse_lease(&text) {
+                        renew_at = Instant::now() + Duration::from_secs((lease / 2).max(1));
+                    } else {
+                        println!("{}", text);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// parse the lease seconds from a subscribe reply, e.g. 200:ok:60
+fn parse_lease(reply: &str) -> Option<u64> {
+    reply
+        .strip_prefix("200:ok:")
+        .and_then(|lease| lease.trim().parse::<u64>().ok())
 }
 
 #[cfg(test)]
@@ -174,6 +320,36 @@ mod tests {
         assert!(resp.is_ok());
     }
 
+    #[test]
+    fn lease_reply() {
+        assert_eq!(parse_lease("200:ok:60"), Some(60));
+        assert_eq!(parse_lease("400:bad-request:subscribe"), None);
+        assert_eq!(parse_lease("message:config:60"), None);
+    }
+
+    #[test]
+    fn listen_loop() {
+        // a stand-in server that replies to the subscribe then pushes a message
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            let (amt, addr) = server.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..amt], b"subscribe config");
+            server.send_to(b"200:ok:60", addr).unwrap();
+            server.send_to(b"message:config:k1 changed", addr).unwrap();
+        });
+
+        let client = Client::new(create_config());
+        let socket = client.create_socket().unwrap();
+        let requests = vec!["subscribe config".to_string()];
+        let count = client
+            .listen_loop(socket, server_address.as_str(), &requests, Some(1))
+            .unwrap();
+        assert_eq!(count, 1);
+        handle.join().unwrap();
+    }
+
     #[test]
     fn show_help() {
         let text = help(true);
@@ -1,17 +1,69 @@
 ///
-use anyhow::Result;
+use crate::trace::{LogSubscriber, REDACTED};
+use anyhow::{anyhow, Result};
 use log::{info, warn};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::io::prelude::*;
 use std::{
+    collections::BTreeMap,
     fs,
     fs::File,
     // io::{BufReader, Read},
-    net::{IpAddr, SocketAddr},
-    str::FromStr,
+    net::{SocketAddr, ToSocketAddrs},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::Duration,
 };
 
-#[derive(Debug, Default, Clone, Deserialize)]
+/// the default max datagram size for requests and replies
+pub const DEFAULT_MAX_PAYLOAD: usize = 1024;
+
+/// the settings that are read when the server starts and need a restart to change
+pub const RESTART_KEYS: &[&str] = &[
+    "name",
+    "version",
+    "host",
+    "port",
+    "data_file",
+    "databases",
+    "max_payload",
+    "pubsub_lease",
+    "key_history",
+    "metrics_address",
+    "access_log",
+];
+
+/// the settings that config set can change while running
+pub const TUNABLE_KEYS: &[&str] = &[
+    "log_level",
+    "log_values",
+    "max_key_length",
+    "max_value_length",
+    "max_keys",
+    "max_memory",
+    "eviction_policy",
+    "max_watchers",
+    "max_subscribers",
+    "script_max_steps",
+    "script_timeout_ms",
+    "slowlog_threshold_us",
+    "slowlog_max_len",
+    "snapshots.keep",
+    "snapshots.max_age",
+    "snapshots.interval",
+];
+
+/// the handle used to replace the log4rs config when it is reloaded
+static LOGGER: OnceLock<log4rs::Handle> = OnceLock::new();
+
+/// the logging config file and log level the logger was last configured with
+static LOG_SOURCE: Mutex<(String, Option<String>)> = Mutex::new((String::new(), None));
+
+/// the prefix of the environment variables that override the config file, e.g. UDP_SERVICE_PORT
+pub const ENV_PREFIX: &str = "UDP_SERVICE_";
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub name: String,
     pub version: String,
@@ -19,13 +71,205 @@ pub struct Config {
     pub port: u16,
     pub logging_config: String,
     pub data_file: Option<String>,
+    pub snapshots: Option<SnapshotConfig>,
+    /// named databases and their data files; the default database uses data_file
+    pub databases: Option<BTreeMap<String, String>>,
+    /// keys that clients send with auth to run admin commands
+    pub admin_keys: Option<Vec<String>>,
+    /// the max udp payload size in bytes for requests and replies
+    pub max_payload: Option<usize>,
+    /// the seconds a subscription lasts before it must be renewed
+    pub pubsub_lease: Option<u64>,
+    /// the max number of watch registrations across all clients
+    pub max_watchers: Option<usize>,
+    /// the max number of channel subscriptions across all clients
+    pub max_subscribers: Option<usize>,
+    /// the number of prior values kept per key for history and revert; zero keeps none
+    pub key_history: Option<usize>,
+    /// the max number of forms an eval script can evaluate
+    pub script_max_steps: Option<usize>,
+    /// the max run time of an eval script in milliseconds
+    pub script_timeout_ms: Option<u64>,
+    /// the approximate max bytes used by all databases; zero or none is unlimited
+    pub max_memory: Option<usize>,
+    /// noeviction, allkeys-lru, allkeys-lfu or volatile-ttl; the default is noeviction
+    pub eviction_policy: Option<String>,
+    /// the max key length in bytes for set and loaddb
+    pub max_key_length: Option<usize>,
+    /// the max value length in bytes for set and loaddb
+    pub max_value_length: Option<usize>,
+    /// the max number of keys in each database
+    pub max_keys: Option<usize>,
+    /// the file the config was read from
+    #[serde(skip)]
+    pub config_file: Option<String>,
+    /// the environment and command line overrides applied to the file, kept for a reload
+    #[serde(skip)]
+    pub overrides: Overrides,
+    /// the address of the http listener for /metrics, /healthz and /readyz, e.g. 127.0.0.1:9100
+    pub metrics_address: Option<String>,
+    /// requests that take longer than this many microseconds go to the slowlog
+    pub slowlog_threshold_us: Option<u64>,
+    /// the number of slowlog entries kept
+    pub slowlog_max_len: Option<usize>,
+    /// log request params and replies; the default is false, which redacts them
+    pub log_values: Option<bool>,
+    /// the root log level, e.g. debug; overrides the level in the logging config
+    pub log_level: Option<String>,
+    /// the access log with one line per request; none turns it off
+    pub access_log: Option<AccessLogConfig>,
+    /// the keys in the config file that are not settings, e.g. a misspelled max_memroy
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct AccessLogConfig {
+    /// the file written to, e.g. logs/access.log; rolled files get a .1, .2, ... suffix
+    pub path: String,
+    /// the size in bytes a file reaches before it is rolled
+    pub max_size: Option<u64>,
+    /// the number of rolled files to keep
+    pub keep: Option<usize>,
+    /// flag the commands that change data with audit
+    pub audit: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SnapshotConfig {
+    /// the folder that holds the versioned snapshot files
+    pub folder: String,
+    /// the number of snapshots to keep
+    pub keep: Option<usize>,
+    /// the max age of a snapshot in seconds
+    pub max_age: Option<u64>,
+    /// the seconds between the snapshots the server takes of every database; none or zero
+    /// takes them only on savedb
+    pub interval: Option<u64>,
+}
+
+/// the settings that can be set from the environment or the command line
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Overrides {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub data_file: Option<String>,
+    pub logging_config: Option<String>,
+}
+
+impl Overrides {
+    /// replace these with the other's overrides
+    pub fn merge(&mut self, other: &Overrides) {
+        if other.host.is_some() {
+            self.host = other.host.clone();
+        }
+        if other.port.is_some() {
+            self.port = other.port;
+        }
+        if other.data_file.is_some() {
+            self.data_file = other.data_file.clone();
+        }
+        if other.logging_config.is_some() {
+            self.logging_config = other.logging_config.clone();
+        }
+    }
+
+    /// read the UDP_SERVICE_* environment variables
+    pub fn from_env() -> Result<Overrides> {
+        Overrides::from_vars(std::env::vars())
+    }
+
+    /// read the overrides from name and value pairs; other names are ignored
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Overrides> {
+        let mut overrides = Overrides::default();
+        for (name, value) in vars {
+            let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+
+            match name {
+                "HOST" => overrides.host = Some(value),
+                "PORT" => {
+                    let port = value.parse::<u16>().map_err(|_| {
+                        anyhow!("{}PORT is not a valid port: {:?}", ENV_PREFIX, value)
+                    })?;
+                    overrides.port = Some(port);
+                }
+                "DATA_FILE" => overrides.data_file = Some(value),
+                "LOGGING_CONFIG" => overrides.logging_config = Some(value),
+                _ => warn!("unknown environment variable: {}{}", ENV_PREFIX, name),
+            }
+        }
+
+        Ok(overrides)
+    }
 }
 
 impl Config {
+    /// read the config file, then apply the environment and then the command line overrides
+    pub fn load(filename: &str, cli: &Overrides) -> Result<Config> {
+        let mut config = Config::read_config(filename)?;
+        let mut overrides = Overrides::from_env()?;
+        overrides.merge(cli);
+        config.apply(&overrides);
+        config.overrides = overrides;
+
+        Ok(config)
+    }
+
+    /// read the config file again with the same overrides
+    pub fn reload(&self) -> Result<Config> {
+        let filename = self
+            .config_file
+            .as_deref()
+            .ok_or_else(|| anyhow!("there is no config file to reload"))?;
+        let mut config = Config::read_config(filename)?;
+        config.apply(&self.overrides);
+        config.overrides = self.overrides.clone();
+
+        Ok(config)
+    }
+
+    /// the names of the settings that differ from the other config
+    pub fn changes(&self, other: &Config) -> Result<Vec<String>> {
+        let table: toml::Table = toml::from_str(&self.to_toml()?)?;
+        let other: toml::Table = toml::from_str(&other.to_toml()?)?;
+        let keys: std::collections::BTreeSet<&String> = table.keys().chain(other.keys()).collect();
+
+        Ok(keys
+            .into_iter()
+            .filter(|key| table.get(*key) != other.get(*key))
+            .map(|key| key.to_string())
+            .collect())
+    }
+
+    /// keep the running values of the settings that need a restart
+    pub fn keep_restart_settings(&mut self, running: &Config) {
+        self.name = running.name.to_string();
+        self.version = running.version.to_string();
+        self.host = running.host.to_string();
+        self.port = running.port;
+        self.data_file = running.data_file.clone();
+        self.databases = running.databases.clone();
+        self.max_payload = running.max_payload;
+        self.pubsub_lease = running.pubsub_lease;
+        self.key_history = running.key_history;
+        self.metrics_address = running.metrics_address.clone();
+        self.access_log = running.access_log.clone();
+    }
+
     // read and parse the config file
     pub fn read_config(filename: &str) -> Result<Config> {
-        let text = fs::read_to_string(filename)?;
-        let config: Config = toml::from_str(&text).unwrap();
+        let text = fs::read_to_string(filename)
+            .map_err(|e| anyhow!("could not read config {}: {}", filename, e))?;
+        let mut unknown_keys = Vec::new();
+        let mut config: Config =
+            serde_ignored::deserialize(toml::Deserializer::new(&text), |path| {
+                unknown_keys.push(key_path(&path))
+            })
+            .map_err(|e| anyhow!("bad config {}: {}", filename, e))?;
+        config.config_file = Some(filename.to_string());
+        config.unknown_keys = unknown_keys;
 
         info!(
             "config: {}, version: {}, host: {}, port: {}",
@@ -44,22 +288,260 @@ impl Config {
             port: self.port,
             logging_config: self.logging_config.to_string(),
             data_file: self.data_file.clone(),
+            snapshots: self.snapshots.clone(),
+            databases: self.databases.clone(),
+            admin_keys: self.admin_keys.clone(),
+            max_payload: self.max_payload,
+            pubsub_lease: self.pubsub_lease,
+            max_watchers: self.max_watchers,
+            max_subscribers: self.max_subscribers,
+            key_history: self.key_history,
+            script_max_steps: self.script_max_steps,
+            script_timeout_ms: self.script_timeout_ms,
+            max_memory: self.max_memory,
+            eviction_policy: self.eviction_policy.clone(),
+            max_key_length: self.max_key_length,
+            max_value_length: self.max_value_length,
+            max_keys: self.max_keys,
+            config_file: self.config_file.clone(),
+            overrides: self.overrides.clone(),
+            metrics_address: self.metrics_address.clone(),
+            slowlog_threshold_us: self.slowlog_threshold_us,
+            slowlog_max_len: self.slowlog_max_len,
+            log_values: self.log_values,
+            log_level: self.log_level.clone(),
+            access_log: self.access_log.clone(),
+            unknown_keys: self.unknown_keys.clone(),
+        }
+    }
+
+    /// replace the settings that are overridden
+    pub fn apply(&mut self, overrides: &Overrides) {
+        if let Some(host) = &overrides.host {
+            self.host = host.to_string();
+        }
+        if let Some(port) = overrides.port {
+            self.port = port;
+        }
+        if let Some(data_file) = &overrides.data_file {
+            self.data_file = Some(data_file.to_string());
+        }
+        if let Some(logging_config) = &overrides.logging_config {
+            self.logging_config = logging_config.to_string();
+        }
+    }
+
+    /// the config as toml
+    pub fn to_toml(&self) -> Result<String> {
+        let text = toml::to_string(self)?;
+
+        Ok(text)
+    }
+
+    /// the config as toml with the admin keys masked, e.g. for --print-config
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        let mut config = self.copy();
+        if let Some(keys) = config.admin_keys.as_mut() {
+            keys.iter_mut().for_each(|key| *key = REDACTED.to_string());
         }
+
+        config.to_toml()
     }
 
     /// start the logger
     pub fn start_logger(&self) -> Result<()> {
-        log4rs::init_file(&self.logging_config, Default::default())?;
+        let handle = log4rs::init_config(self.log_config()?)?;
+        let _ = LOGGER.set(handle);
+        if let Some(rate) = refresh_rate(&self.logging_config)? {
+            watch_log_config(rate);
+        }
+        LogSubscriber::install();
         info!("START THE SERVICE LOG: {}", "-".repeat(80));
 
         Ok(())
     }
 
-    /// return the socket address that server listens on
-    pub fn socket_address(&self) -> SocketAddr {
-        let v4 = IpAddr::from_str(self.host.as_str()).expect("a good host name from config");
+    /// replace the running log config with the logging config file, e.g. to change levels
+    pub fn reload_logger(&self) -> Result<()> {
+        let handle = LOGGER
+            .get()
+            .ok_or_else(|| anyhow!("the logger was not started"))?;
+        handle.set_config(self.log_config()?);
+        info!("reloaded the log config: {}", self.logging_config);
 
-        SocketAddr::new(v4, self.port)
+        Ok(())
+    }
+
+    /// read the logging config file and set the log level
+    fn log_config(&self) -> Result<log4rs::Config> {
+        let config = load_log_config(&self.logging_config, self.log_level.as_deref())?;
+        *LOG_SOURCE.lock().unwrap_or_else(|e| e.into_inner()) =
+            (self.logging_config.to_string(), self.log_level.clone());
+
+        Ok(config)
+    }
+
+    /// the value of the setting, e.g. port or snapshots.keep; none if it is not set
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        let (key, field) = match name.split_once('.') {
+            Some((key, field)) => (key, Some(field)),
+            None => (name, None),
+        };
+        if !Config::known(name) {
+            return Err(anyhow!("unknown setting: {}", name));
+        }
+
+        let table: toml::Table = toml::from_str(&self.to_toml()?)?;
+        let value = table
+            .get(key)
+            .and_then(|value| field.map_or(Some(value), |f| value.get(f)));
+
+        Ok(value.map(|value| match value.as_str() {
+            Some(text) => text.to_string(),
+            None => value.to_string(),
+        }))
+    }
+
+    /// change a setting that can be tuned while running
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        if !TUNABLE_KEYS.contains(&name) {
+            return Err(anyhow!("{} can't be set while running", name));
+        }
+
+        match name {
+            "log_level" => {
+                parse::<log::LevelFilter>(name, value)?;
+                self.log_level = Some(value.to_lowercase());
+            }
+            "log_values" => self.log_values = Some(parse(name, value)?),
+            "max_key_length" => self.max_key_length = Some(parse(name, value)?),
+            "max_value_length" => self.max_value_length = Some(parse(name, value)?),
+            "max_keys" => self.max_keys = Some(parse(name, value)?),
+            "max_memory" => self.max_memory = Some(parse(name, value)?),
+            "eviction_policy" => {
+                parse::<crate::eviction::Policy>(name, value)?;
+                self.eviction_policy = Some(value.to_string());
+            }
+            "max_watchers" => self.max_watchers = Some(parse(name, value)?),
+            "max_subscribers" => self.max_subscribers = Some(parse(name, value)?),
+            "script_max_steps" => self.script_max_steps = Some(parse(name, value)?),
+            "script_timeout_ms" => self.script_timeout_ms = Some(parse(name, value)?),
+            "slowlog_threshold_us" => self.slowlog_threshold_us = Some(parse(name, value)?),
+            "slowlog_max_len" => self.slowlog_max_len = Some(parse(name, value)?),
+            _ => {
+                let snapshots = self
+                    .snapshots
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("snapshots are not configured"))?;
+                match name {
+                    "snapshots.keep" => snapshots.keep = Some(parse(name, value)?),
+                    "snapshots.max_age" => snapshots.max_age = Some(parse(name, value)?),
+                    _ => snapshots.interval = Some(parse(name, value)?),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// write the settings back to the config file; the settings that need a restart and the
+    /// overridden ones keep their values from the file, and comments are not kept
+    pub fn rewrite(&self) -> Result<String> {
+        let filename = self
+            .config_file
+            .as_deref()
+            .ok_or_else(|| anyhow!("there is no config file to rewrite"))?;
+
+        let file = Config::read_config(filename)?;
+        let mut config = self.copy();
+        // the running values of these may be stale after a reload; host, port and data_file
+        // are also the overridden ones
+        config.keep_restart_settings(&file);
+        if self.overrides.logging_config.is_some() {
+            config.logging_config = file.logging_config;
+        }
+
+        let temp = format!("{}.tmp", filename);
+        fs::write(&temp, config.to_toml()?)?;
+        fs::rename(&temp, filename)?;
+        info!("rewrote the config: {}", filename);
+
+        Ok(filename.to_string())
+    }
+
+    /// the configured max payload or the default
+    pub fn max_payload(&self) -> usize {
+        self.max_payload.unwrap_or(DEFAULT_MAX_PAYLOAD)
+    }
+
+    /// return the socket address that server listens on; the host may be an ip or a hostname
+    pub fn socket_address(&self) -> Result<SocketAddr> {
+        (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| anyhow!("host {} could not be resolved: {}", self.host, e))?
+            .next()
+            .ok_or_else(|| anyhow!("host {} has no addresses", self.host))
+    }
+
+    /// check the config and return all of the problems found; empty when it is good
+    pub fn validate(&self) -> Vec<String> {
+        self.problems()
+            .into_iter()
+            .map(|(_, problem)| problem)
+            .collect()
+    }
+
+    /// the problems found by validate, each with the setting it is about
+    pub fn problems(&self) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+        let mut problem = |setting: &str, text: String| problems.push((setting.to_string(), text));
+
+        if let Err(e) = self.socket_address() {
+            problem("host", e.to_string());
+        }
+        if self.port == 0 {
+            problem("port", "port must not be 0".to_string());
+        }
+        if !Path::new(&self.logging_config).is_file() {
+            problem(
+                "logging_config",
+                format!("logging_config {} not found", self.logging_config),
+            );
+        }
+        if let Some(data_file) = &self.data_file {
+            if let Err(e) = File::open(data_file) {
+                problem(
+                    "data_file",
+                    format!("data_file {} can't be read: {}", data_file, e),
+                );
+            }
+        }
+        if let Some(policy) = &self.eviction_policy {
+            if let Err(e) = policy.parse::<crate::eviction::Policy>() {
+                problem("eviction_policy", e.to_string());
+            }
+        }
+        for key in &self.unknown_keys {
+            problem(key, format!("unknown key: {}", key));
+        }
+
+        problems
+    }
+
+    /// true if the name is a setting, e.g. port or snapshots.keep
+    fn known(name: &str) -> bool {
+        match name.split_once('.') {
+            None => field_names::<Config>().contains(&name),
+            Some(("snapshots", field)) => field_names::<SnapshotConfig>().contains(&field),
+            Some(("access_log", field)) => field_names::<AccessLogConfig>().contains(&field),
+            _ => false,
+        }
+    }
+
+    /// true if the setting is known and changes on a reload, e.g. max_keys but not port
+    pub fn reloadable(name: &str) -> bool {
+        let key = name.split('.').next().unwrap_or(name);
+        Config::known(name) && !RESTART_KEYS.contains(&key)
     }
 
     /// write the pid file
@@ -85,6 +567,134 @@ impl Config {
     }
 }
 
+/// read the logging config file and set the log level, if any
+fn load_log_config(filename: &str, log_level: Option<&str>) -> Result<log4rs::Config> {
+    let mut config = log4rs::config::load_config_file(filename, Default::default())?;
+    if let Some(level) = log_level {
+        let level = level
+            .parse::<log::LevelFilter>()
+            .map_err(|_| anyhow!("log_level {} is not a valid level", level))?;
+        config.root_mut().set_level(level);
+    }
+
+    Ok(config)
+}
+
+/// the refresh_rate in the logging config file; none when the file is not watched
+fn refresh_rate(filename: &str) -> Result<Option<Duration>> {
+    let text = fs::read_to_string(filename)?;
+    let raw: log4rs::config::RawConfig = serde_yaml::from_str(&text)?;
+
+    Ok(raw.refresh_rate())
+}
+
+/// check the logging config file every refresh_rate, as log4rs::init_file does, and apply it
+/// when it changes; the file and level are the ones the logger was last configured with
+fn watch_log_config(rate: Duration) {
+    let modified = |filename: &str| fs::metadata(filename).and_then(|m| m.modified()).ok();
+    thread::spawn(move || {
+        let source = || LOG_SOURCE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut last = modified(&source().0);
+        loop {
+            thread::sleep(rate);
+            let (filename, log_level) = source();
+            let current = modified(&filename);
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            match load_log_config(&filename, log_level.as_deref()) {
+                Ok(config) => {
+                    if let Some(handle) = LOGGER.get() {
+                        handle.set_config(config);
+                        info!("the log config changed, reloaded: {}", filename);
+                    }
+                }
+                Err(e) => warn!("log config not reloaded: {}", e),
+            }
+        }
+    });
+}
+
+/// parse the value of a setting
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .parse::<T>()
+        .map_err(|_| anyhow!("{} is not a valid {}", value, name))
+}
+
+/// the dotted name of an ignored key, e.g. snapshots.kept
+fn key_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Map { parent, key } => match key_path(parent) {
+            parent if parent.is_empty() => key.to_string(),
+            parent => format!("{}.{}", parent, key),
+        },
+        Path::Seq { parent, index } => format!("{}.{}", key_path(parent), index),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+/// the names of the fields a struct reads, e.g. the settings of Config; the derived
+/// Deserialize passes them to deserialize_struct, which hands them back as the error
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    match T::deserialize(FieldNames) {
+        Err(FieldNamesFound(fields)) => fields,
+        Ok(_) => &[],
+    }
+}
+
+/// a deserializer that only answers with the field names of a struct
+struct FieldNames;
+
+#[derive(Debug)]
+struct FieldNamesFound(&'static [&'static str]);
+
+impl std::fmt::Display for FieldNamesFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "fields: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for FieldNamesFound {}
+
+impl serde::de::Error for FieldNamesFound {
+    fn custom<T: std::fmt::Display>(_msg: T) -> Self {
+        FieldNamesFound(&[])
+    }
+}
+
+impl<'de> serde::Deserializer<'de> for FieldNames {
+    type Error = FieldNamesFound;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> std::result::Result<V::Value, FieldNamesFound> {
+        Err(FieldNamesFound(&[]))
+    }
+
+    fn deserialize_struct<V: serde::de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> std::result::Result<V::Value, FieldNamesFound> {
+        Err(FieldNamesFound(fields))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,6 +704,7 @@ mod tests {
         let config = Config::read_config("tests/server-config.toml").unwrap();
         assert!(!config.name.is_empty());
         assert!(!config.version.is_empty());
+        assert_eq!(config.config_file.unwrap(), "tests/server-config.toml");
     }
 
     #[test]
@@ -111,12 +722,272 @@ mod tests {
         assert!(resp.is_ok());
     }
 
+    #[test]
+    fn log_refresh_rate() {
+        assert_eq!(refresh_rate("config/console.yaml").unwrap(), None);
+
+        let filename = "tests/refresh-rate-out/console.yaml";
+        fs::create_dir_all("tests/refresh-rate-out").unwrap();
+        let text = fs::read_to_string("config/console.yaml").unwrap();
+        fs::write(filename, format!("refresh_rate: 30 seconds\n{}", text)).unwrap();
+        assert_eq!(
+            refresh_rate(filename).unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        fs::remove_dir_all("tests/refresh-rate-out").unwrap();
+    }
+
+    #[test]
+    fn bad_config() {
+        assert!(Config::read_config("tests/not-a-config.toml").is_err());
+        let resp = Config::read_config("tests/users-ref.kv");
+        assert!(resp.unwrap_err().to_string().starts_with("bad config"));
+    }
+
+    #[test]
+    fn overrides() {
+        let vars = vec![
+            ("UDP_SERVICE_HOST".to_string(), "0.0.0.0".to_string()),
+            ("UDP_SERVICE_PORT".to_string(), "22300".to_string()),
+            (
+                "UDP_SERVICE_DATA_FILE".to_string(),
+                "data/config.kv".to_string(),
+            ),
+            ("HOME".to_string(), "/home/dpw".to_string()),
+        ];
+        let env = Overrides::from_vars(vars).unwrap();
+        assert_eq!(env.port, Some(22300));
+        assert_eq!(env.logging_config, None);
+
+        let vars = vec![("UDP_SERVICE_PORT".to_string(), "flarb".to_string())];
+        assert!(Overrides::from_vars(vars).is_err());
+
+        // the command line is applied after the environment
+        let mut config = Config::read_config("tests/server-config.toml").unwrap();
+        config.apply(&env);
+        config.apply(&Overrides {
+            port: Some(22400),
+            ..Overrides::default()
+        });
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.port, 22400);
+        assert_eq!(config.data_file, Some("data/config.kv".to_string()));
+        assert_eq!(config.logging_config, "config/console.yaml");
+    }
+
+    #[test]
+    fn reload_changes() {
+        let cli = Overrides {
+            port: Some(22300),
+            ..Overrides::default()
+        };
+        let config = Config::load("tests/server-config.toml", &cli).unwrap();
+        let mut reloaded = config.reload().unwrap();
+        assert_eq!(reloaded.port, 22300);
+        assert!(config.changes(&reloaded).unwrap().is_empty());
+
+        reloaded.port = 22400;
+        reloaded.max_keys = Some(10);
+        assert_eq!(config.changes(&reloaded).unwrap(), vec!["max_keys", "port"]);
+
+        reloaded.keep_restart_settings(&config);
+        assert_eq!(config.changes(&reloaded).unwrap(), vec!["max_keys"]);
+        assert!(Config::default().reload().is_err());
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut config = Config::read_config("config/server-config.toml").unwrap();
+        assert_eq!(config.get("port").unwrap(), Some("22200".to_string()));
+        assert_eq!(config.get("host").unwrap(), Some("0.0.0.0".to_string()));
+        assert_eq!(
+            config.get("snapshots.keep").unwrap(),
+            Some("10".to_string())
+        );
+        assert_eq!(config.get("max_keys").unwrap(), None);
+        assert!(config.get("snapshots.kept").is_err());
+        assert!(config.get("flarb").is_err());
+
+        config.set("max_keys", "100").unwrap();
+        config.set("log_level", "DEBUG").unwrap();
+        config.set("snapshots.max_age", "3600").unwrap();
+        config.set("snapshots.interval", "600").unwrap();
+        assert_eq!(config.get("max_keys").unwrap(), Some("100".to_string()));
+        assert_eq!(config.get("log_level").unwrap(), Some("debug".to_string()));
+        assert_eq!(config.snapshots.as_ref().unwrap().max_age, Some(3600));
+        assert_eq!(
+            config.get("snapshots.interval").unwrap(),
+            Some("600".to_string())
+        );
+        assert!(config.set("snapshots.interval", "often").is_err());
+
+        let resp = config.set("max_keys", "many");
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "many is not a valid max_keys"
+        );
+        assert!(config.set("log_level", "loud").is_err());
+        assert!(config.set("eviction_policy", "random").is_err());
+        let resp = config.set("port", "22300");
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "port can't be set while running"
+        );
+
+        let mut config = Config::read_config("tests/server-config.toml").unwrap();
+        assert!(config.set("snapshots.keep", "3").is_err());
+    }
+
+    #[test]
+    fn rewrite() {
+        let filename = "tests/rewrite-config-out.toml";
+        fs::copy("tests/server-config.toml", filename).unwrap();
+        let cli = Overrides {
+            port: Some(22300),
+            ..Overrides::default()
+        };
+        let mut config = Config::load(filename, &cli).unwrap();
+        config.set("max_value_length", "4096").unwrap();
+        assert_eq!(config.rewrite().unwrap(), filename);
+
+        // the override is not written to the file
+        let written = Config::read_config(filename).unwrap();
+        assert_eq!(written.port, 28400);
+        assert_eq!(written.max_value_length, Some(4096));
+        assert_eq!(written.logging_config, "config/console.yaml");
+        fs::remove_file(filename).unwrap();
+
+        assert!(Config::default().rewrite().is_err());
+    }
+
+    #[test]
+    fn print_config() {
+        let config = Config::read_config("config/server-config.toml").unwrap();
+        let text = config.to_toml().unwrap();
+        assert!(text.contains("port = 22200"));
+        assert!(text.contains("[access_log]"));
+
+        let copy: Config = toml::from_str(&text).unwrap();
+        assert_eq!(copy.to_toml().unwrap(), text);
+
+        let mut config = config;
+        config.admin_keys = Some(vec!["secret-key".to_string()]);
+        let text = config.to_redacted_toml().unwrap();
+        assert!(!text.contains("secret-key"));
+        assert!(text.contains(r#"admin_keys = ["(redacted)"]"#));
+        assert!(config.to_toml().unwrap().contains("secret-key"));
+    }
+
+    #[test]
+    fn logging_configs() {
+        for file in [
+            "config/console.yaml",
+            "config/rolling.yaml",
+            "config/json.yaml",
+        ] {
+            let resp = log4rs::config::load_config_file(file, Default::default());
+            assert!(resp.is_ok(), "{}", file);
+        }
+    }
+
     #[test]
     fn socket_address() {
         let config = Config::read_config("tests/server-config.toml").unwrap();
-        let addr = config.socket_address();
+        let addr = config.socket_address().unwrap();
 
         assert_eq!(format!("{}", addr), "127.0.0.1:28400");
+
+        let config = Config {
+            host: "localhost".to_string(),
+            port: 28400,
+            ..Config::default()
+        };
+        assert_eq!(config.socket_address().unwrap().port(), 28400);
+    }
+
+    #[test]
+    fn validate() {
+        let config = Config::read_config("config/server-config.toml").unwrap();
+        assert!(config.validate().is_empty(), "{:?}", config.validate());
+
+        let config = Config::read_config("tests/bad-config.toml").unwrap();
+        let reloadable: Vec<String> = config
+            .problems()
+            .into_iter()
+            .filter(|(setting, _)| Config::reloadable(setting))
+            .map(|(setting, _)| setting)
+            .collect();
+        assert_eq!(reloadable, vec!["logging_config", "eviction_policy"]);
+        let problems = config.validate();
+        assert_eq!(
+            problems,
+            vec![
+                "port must not be 0",
+                "logging_config config/missing.yaml not found",
+                "data_file data/missing.kv can't be read: No such file or directory (os error 2)",
+                "unknown eviction policy: random",
+                "unknown key: max_memroy",
+                "unknown key: snapshots.kept",
+            ]
+        );
+
+        let config = Config {
+            host: "not a host name".to_string(),
+            port: 28400,
+            ..Config::default()
+        };
+        assert!(config.validate()[0].starts_with("host not a host name could not be resolved"));
+    }
+
+    #[test]
+    fn config_keys() {
+        // every setting is a known key
+        let config = Config {
+            data_file: Some(String::new()),
+            snapshots: Some(SnapshotConfig {
+                keep: Some(1),
+                max_age: Some(1),
+                interval: Some(1),
+                ..SnapshotConfig::default()
+            }),
+            databases: Some(BTreeMap::new()),
+            admin_keys: Some(vec![]),
+            max_payload: Some(1),
+            pubsub_lease: Some(1),
+            max_watchers: Some(1),
+            max_subscribers: Some(1),
+            key_history: Some(1),
+            script_max_steps: Some(1),
+            script_timeout_ms: Some(1),
+            max_memory: Some(1),
+            eviction_policy: Some(String::new()),
+            max_key_length: Some(1),
+            max_value_length: Some(1),
+            max_keys: Some(1),
+            metrics_address: Some(String::new()),
+            slowlog_threshold_us: Some(1),
+            slowlog_max_len: Some(1),
+            log_values: Some(true),
+            log_level: Some(String::new()),
+            access_log: Some(AccessLogConfig {
+                max_size: Some(1),
+                keep: Some(1),
+                audit: Some(true),
+                ..AccessLogConfig::default()
+            }),
+            ..Config::default()
+        };
+        let text = config.to_toml().unwrap();
+        let table: toml::Table = toml::from_str(&text).unwrap();
+        assert_eq!(table.len(), field_names::<Config>().len());
+        let _: Config = serde_ignored::deserialize(toml::Deserializer::new(&text), |path| {
+            panic!("unknown key: {}", path)
+        })
+        .unwrap();
+        assert_eq!(
+            field_names::<SnapshotConfig>(),
+            ["folder", "keep", "max_age", "interval"]
+        );
     }
 
     #[test]
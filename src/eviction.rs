@@ -0,0 +1,312 @@
+/// approximate memory accounting and the eviction policies used when max_memory is reached
+///
+/// each key is counted as the length of its key and value plus a fixed overhead.  like redis,
+/// lru and lfu are approximated by choosing the best victim from a small random sample of keys.
+use anyhow::{anyhow, Result};
+use rand::seq::index;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// the bytes counted for each key on top of the key and value lengths
+pub const KEY_OVERHEAD: usize = 48;
+
+/// the number of keys sampled to choose a victim
+const EVICTION_SAMPLES: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// refuse writes that need memory
+    #[default]
+    NoEviction,
+    /// evict the least recently used key
+    AllKeysLru,
+    /// evict the least frequently used key
+    AllKeysLfu,
+    /// evict the key closest to expiring; keys have no ttl so nothing is volatile and this
+    /// refuses writes like noeviction
+    VolatileTtl,
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "noeviction" => Ok(Policy::NoEviction),
+            "allkeys-lru" => Ok(Policy::AllKeysLru),
+            "allkeys-lfu" => Ok(Policy::AllKeysLfu),
+            "volatile-ttl" => Ok(Policy::VolatileTtl),
+            _ => Err(anyhow!("unknown eviction policy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Policy::NoEviction => "noeviction",
+            Policy::AllKeysLru => "allkeys-lru",
+            Policy::AllKeysLfu => "allkeys-lfu",
+            Policy::VolatileTtl => "volatile-ttl",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct KeyUsage {
+    size: usize,
+    last_access: u64,
+    hits: u64,
+    /// the index of the key in slots
+    slot: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Memory {
+    max_memory: usize,
+    policy: Policy,
+    used: usize,
+    databases: HashMap<String, HashMap<String, KeyUsage>>,
+    /// every counted db and key, so a sample can be picked at random
+    slots: Vec<(String, String)>,
+    clock: u64,
+    evicted: u64,
+    rejected: u64,
+}
+
+impl Memory {
+    /// create the accounting with the limit in bytes; zero is unlimited
+    pub fn create(max_memory: usize, policy: Policy) -> Memory {
+        Memory {
+            max_memory,
+            policy,
+            ..Memory::default()
+        }
+    }
+
+    /// change the limit and policy, keeping the usage counted so far
+    pub fn configure(&mut self, max_memory: usize, policy: Policy) {
+        self.max_memory = max_memory;
+        self.policy = policy;
+    }
+
+    /// the approximate bytes used by all databases
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn max_memory(&self) -> usize {
+        self.max_memory
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// the number of keys evicted
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// the number of writes refused
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// true if there is a limit and it has been reached
+    pub fn is_full(&self) -> bool {
+        self.max_memory > 0 && self.used >= self.max_memory
+    }
+
+    /// true if there is no limit or a write of the incoming bytes stays within it
+    pub fn fits(&self, incoming: usize) -> bool {
+        self.max_memory == 0 || self.used + incoming <= self.max_memory
+    }
+
+    /// true if keys can be evicted to make room
+    pub fn can_evict(&self) -> bool {
+        matches!(self.policy, Policy::AllKeysLru | Policy::AllKeysLfu)
+    }
+
+    /// count a refused write
+    pub fn reject(&mut self) {
+        self.rejected += 1;
+    }
+
+    /// record an access to the key
+    pub fn touch(&mut self, db: &str, key: &str) {
+        if let Some(usage) = self
+            .databases
+            .get_mut(db)
+            .and_then(|keys| keys.get_mut(key))
+        {
+            self.clock += 1;
+            usage.last_access = self.clock;
+            usage.hits += 1;
+        }
+    }
+
+    /// record a write of the key's new value length, or None when the key was removed
+    pub fn update(&mut self, db: &str, key: &str, len: Option<usize>) {
+        let keys = self.databases.entry(db.to_string()).or_default();
+        let Some(len) = len else {
+            if let Some(usage) = keys.remove(key) {
+                self.used -= usage.size;
+                self.slots.swap_remove(usage.slot);
+                if let Some((db, key)) = self.slots.get(usage.slot) {
+                    if let Some(moved) = self.databases.get_mut(db).and_then(|k| k.get_mut(key)) {
+                        moved.slot = usage.slot;
+                    }
+                }
+            }
+            return;
+        };
+
+        self.clock += 1;
+        let slots = &mut self.slots;
+        let usage = keys.entry(key.to_string()).or_insert_with(|| {
+            slots.push((db.to_string(), key.to_string()));
+            KeyUsage {
+                slot: slots.len() - 1,
+                ..KeyUsage::default()
+            }
+        });
+        self.used -= usage.size;
+        usage.size = key.len() + len + KEY_OVERHEAD;
+        usage.last_access = self.clock;
+        usage.hits += 1;
+        self.used += usage.size;
+    }
+
+    /// recount the database from its keys and value lengths after a bulk change
+    pub fn reset(&mut self, db: &str, keys: Vec<(String, usize)>) {
+        if let Some(old) = self.databases.remove(db) {
+            self.used -= old.values().map(|u| u.size).sum::<usize>();
+            self.slots.retain(|(slot_db, _)| slot_db != db);
+            for (slot, (db, key)) in self.slots.iter().enumerate() {
+                if let Some(usage) = self.databases.get_mut(db).and_then(|k| k.get_mut(key)) {
+                    usage.slot = slot;
+                }
+            }
+        }
+
+        for (key, len) in keys {
+            self.update(db, &key, Some(len));
+        }
+    }
+
+    /// choose the database and key to evict, and count it as evicted
+    pub fn victim(&mut self) -> Option<(String, String)> {
+        let samples = EVICTION_SAMPLES.min(self.slots.len());
+        let candidates = index::sample(&mut rand::thread_rng(), self.slots.len(), samples)
+            .into_iter()
+            .filter_map(|slot| {
+                let (db, key) = &self.slots[slot];
+                let usage = self.databases.get(db)?.get(key)?;
+                Some((db, key, usage))
+            });
+
+        let victim = match self.policy {
+            Policy::AllKeysLru => candidates.min_by_key(|(_, _, u)| u.last_access),
+            Policy::AllKeysLfu => candidates.min_by_key(|(_, _, u)| (u.hits, u.last_access)),
+            _ => None,
+        }
+        .map(|(db, key, _)| (db.to_string(), key.to_string()));
+
+        if victim.is_some() {
+            self.evicted += 1;
+        }
+
+        victim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        for name in ["noeviction", "allkeys-lru", "allkeys-lfu", "volatile-ttl"] {
+            let policy: Policy = name.parse().unwrap();
+            assert_eq!(policy.to_string(), name);
+        }
+        assert!("random".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn accounting() {
+        let mut memory = Memory::create(0, Policy::NoEviction);
+        memory.update("default", "k1", Some(10));
+        memory.update("default", "k2", Some(20));
+        assert_eq!(memory.used(), 2 + 10 + 2 + 20 + 2 * KEY_OVERHEAD);
+
+        memory.update("default", "k1", Some(5));
+        memory.update("default", "k2", None);
+        assert_eq!(memory.used(), 2 + 5 + KEY_OVERHEAD);
+        assert!(!memory.is_full());
+
+        memory.reset("default", vec![("a".to_string(), 1)]);
+        memory.reset("other", vec![("b".to_string(), 1)]);
+        assert_eq!(memory.used(), 2 * (2 + KEY_OVERHEAD));
+        memory.reset("default", vec![]);
+        assert_eq!(memory.used(), 2 + KEY_OVERHEAD);
+        assert_eq!(memory.slots, vec![("other".to_string(), "b".to_string())]);
+
+        let mut memory = Memory::create(100, Policy::AllKeysLru);
+        memory.update("default", "k1", Some(10));
+        assert!(memory.fits(100 - memory.used()));
+        assert!(!memory.fits(101 - memory.used()));
+    }
+
+    #[test]
+    fn random_samples() {
+        let mut memory = Memory::create(1, Policy::AllKeysLru);
+        for n in 0..1000 {
+            memory.update("default", &format!("k{}", n), Some(1));
+        }
+        for n in (0..1000).step_by(2) {
+            memory.update("default", &format!("k{}", n), None);
+        }
+        assert_eq!(memory.slots.len(), 500);
+        for (slot, (db, key)) in memory.slots.iter().enumerate() {
+            assert_eq!(memory.databases[db][key].slot, slot);
+        }
+
+        // each victim is the least recently used key of a new random sample
+        let victims: std::collections::HashSet<String> = (0..20)
+            .filter_map(|_| memory.victim())
+            .map(|v| v.1)
+            .collect();
+        assert!(victims.len() > 1);
+    }
+
+    #[test]
+    fn lru_and_lfu_victims() {
+        let mut memory = Memory::create(100, Policy::AllKeysLru);
+        memory.update("default", "k1", Some(10));
+        memory.update("default", "k2", Some(10));
+        memory.touch("default", "k1");
+        assert!(memory.is_full());
+        assert_eq!(memory.victim(), Some(("default".into(), "k2".into())));
+        memory.configure(0, Policy::NoEviction);
+        assert!(!memory.is_full());
+        assert_eq!(memory.used(), 2 * (2 + 10 + KEY_OVERHEAD));
+
+        let mut memory = Memory::create(100, Policy::AllKeysLfu);
+        memory.update("default", "k1", Some(10));
+        memory.update("default", "k2", Some(10));
+        memory.touch("default", "k2");
+        memory.touch("default", "k2");
+        memory.touch("default", "k1");
+        assert_eq!(memory.victim(), Some(("default".into(), "k1".into())));
+        assert_eq!(memory.evicted(), 1);
+
+        let mut memory = Memory::create(100, Policy::VolatileTtl);
+        memory.update("default", "k1", Some(10));
+        assert!(!memory.can_evict());
+        assert_eq!(memory.victim(), None);
+    }
+}
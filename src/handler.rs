@@ -1,9 +1,22 @@
 ///
+use crate::config::{Config, DEFAULT_MAX_PAYLOAD, RESTART_KEYS};
+use crate::eviction::{Memory, Policy, KEY_OVERHEAD};
+use crate::meta::{KeyMeta, Metadata};
+use crate::metrics::Exposition;
 use crate::parsers;
+use crate::pubsub::{Subscriptions, DEFAULT_LEASE};
+use crate::script::{self, Atom, Limits, ScriptError};
+use crate::slowlog::{self, SlowLog};
+use crate::snapshot::Snapshots;
+use crate::stats::Stats;
+use crate::trace;
+use crate::values::{by_score, index_range, Value};
 use anyhow::{anyhow, Result};
-use log::{error, info};
+use log::{error, info, warn};
 use service_uptime::status::ServiceStatus;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tiny_kv::db::DataStore;
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -30,6 +43,34 @@ impl Request {
             }
         }
     }
+
+    /// build a request from words that are already split, e.g. an exec command or script call;
+    /// the first word is the key and the rest are joined as the value, so either may hold spaces
+    pub fn from_parts(cmd: &str, words: &[String]) -> Result<Request> {
+        if cmd.is_empty() {
+            return Err(anyhow!("empty request"));
+        }
+
+        let key = words.first().cloned().unwrap_or_default();
+        let params = if words.len() > 1 {
+            vec![key, words[1..].join(" ")]
+        } else {
+            vec![key]
+        };
+
+        Ok(Request {
+            cmd: cmd.to_string(),
+            params,
+        })
+    }
+
+    /// return all of the params split on whitespace
+    pub fn args(&self) -> Vec<&str> {
+        self.params
+            .iter()
+            .flat_map(|p| p.split_whitespace())
+            .collect()
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -47,6 +88,14 @@ impl Status {
         }
     }
 
+    pub fn partial() -> Status {
+        let code: u16 = 206;
+        Status {
+            code,
+            description: "partial".to_string(),
+        }
+    }
+
     pub fn bad_request() -> Status {
         let code: u16 = 400;
         Status {
@@ -55,6 +104,14 @@ impl Status {
         }
     }
 
+    pub fn forbidden() -> Status {
+        let code: u16 = 403;
+        Status {
+            code,
+            description: "forbidden".to_string(),
+        }
+    }
+
     pub fn not_found() -> Status {
         let code: u16 = 404;
         Status {
@@ -62,6 +119,70 @@ impl Status {
             description: "not-found".to_string(),
         }
     }
+
+    pub fn wrong_type() -> Status {
+        let code: u16 = 409;
+        Status {
+            code,
+            description: "wrong-type".to_string(),
+        }
+    }
+
+    pub fn precondition_failed() -> Status {
+        let code: u16 = 412;
+        Status {
+            code,
+            description: "precondition-failed".to_string(),
+        }
+    }
+
+    pub fn too_many() -> Status {
+        let code: u16 = 429;
+        Status {
+            code,
+            description: "too-many".to_string(),
+        }
+    }
+
+    pub fn too_large() -> Status {
+        let code: u16 = 413;
+        Status {
+            code,
+            description: "too-large".to_string(),
+        }
+    }
+
+    pub fn key_too_long() -> Status {
+        let code: u16 = 414;
+        Status {
+            code,
+            description: "key-too-long".to_string(),
+        }
+    }
+
+    pub fn value_too_long() -> Status {
+        let code: u16 = 415;
+        Status {
+            code,
+            description: "value-too-long".to_string(),
+        }
+    }
+
+    pub fn too_many_keys() -> Status {
+        let code: u16 = 416;
+        Status {
+            code,
+            description: "too-many-keys".to_string(),
+        }
+    }
+
+    pub fn out_of_memory() -> Status {
+        let code: u16 = 507;
+        Status {
+            code,
+            description: "out-of-memory".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -101,163 +222,2194 @@ impl Response {
     }
 }
 
+/// the name of the database used when a client has not selected one
+pub const DEFAULT_DB: &str = "default";
+
+/// the seconds a client's database selection lasts without a request from the client
+const SELECTION_IDLE: u64 = 3600;
+
+/// the topic that holds the monitoring clients
+const MONITOR_TOPIC: &str = "monitor";
+
+/// the default max number of watch registrations across all clients
+pub const DEFAULT_MAX_WATCHERS: usize = 1000;
+
+/// the default max number of channel subscriptions across all clients
+pub const DEFAULT_MAX_SUBSCRIBERS: usize = 1000;
+
+/// a key's value and metadata saved before an exec or eval changes it
+type Undo = (String, Option<Vec<u8>>, Option<KeyMeta>);
+
+/// the sections shown by info, in order
+const INFO_SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "keyspace",
+    "commandstats",
+];
+
+/// the command changes data, so it is flagged for audit in the access log
+const WRITES: u8 = 1;
+
+/// the command needs memory, so it is refused, or evicts keys, when max_memory is reached
+const GROWS_MEMORY: u8 = 2;
+
+/// the command can run inside exec and eval
+const EXEC_ALLOWED: u8 = 4;
+
+/// runs a command for the client
+type Run = fn(&mut Handler, Option<SocketAddr>, &Request) -> Response;
+
+/// a command, its flags and what runs it
+#[derive(Debug)]
+struct Command {
+    name: &'static str,
+    flags: u8,
+    run: Run,
+}
+
+impl Command {
+    const fn new(name: &'static str, flags: u8, run: Run) -> Command {
+        Command { name, flags, run }
+    }
+
+    /// the command named by cmd, if there is one
+    fn find(cmd: &str) -> Option<&'static Command> {
+        COMMAND_TABLE.iter().find(|command| command.name == cmd)
+    }
+
+    fn writes(&self) -> bool {
+        self.flags & WRITES != 0
+    }
+
+    fn grows_memory(&self) -> bool {
+        self.flags & GROWS_MEMORY != 0
+    }
+
+    fn exec_allowed(&self) -> bool {
+        self.flags & EXEC_ALLOWED != 0
+    }
+}
+
+/// every command, used to dispatch requests and name the counters; anything else is a bad
+/// request and counted as unknown
+const COMMAND_TABLE: &[Command] = &[
+    Command::new("ping", 0, Handler::server_request),
+    Command::new("now", 0, Handler::server_request),
+    Command::new("now_ns", 0, Handler::server_request),
+    Command::new("status", 0, Handler::server_request),
+    Command::new("info", 0, Handler::server_request),
+    Command::new("stats", 0, Handler::server_request),
+    Command::new("slowlog", 0, Handler::slowlog_request),
+    Command::new("monitor", 0, Handler::monitor_request),
+    Command::new("unmonitor", 0, Handler::monitor_request),
+    Command::new("config", 0, Handler::config_request),
+    Command::new("get", EXEC_ALLOWED, Handler::string_request),
+    Command::new(
+        "set",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::string_request,
+    ),
+    Command::new("del", WRITES | EXEC_ALLOWED, Handler::string_request),
+    Command::new("dbsize", 0, Handler::database_request),
+    Command::new("keys", 0, Handler::database_request),
+    Command::new(
+        "loaddb",
+        WRITES | GROWS_MEMORY,
+        Handler::persistence_request,
+    ),
+    Command::new("savedb", 0, Handler::persistence_request),
+    Command::new("listsnapshots", 0, Handler::persistence_request),
+    Command::new(
+        "restore",
+        WRITES | GROWS_MEMORY,
+        Handler::persistence_request,
+    ),
+    Command::new("select", 0, Handler::database_request),
+    Command::new("databases", 0, Handler::database_request),
+    Command::new("auth", 0, Handler::server_request),
+    Command::new("flushdb", WRITES, Handler::database_request),
+    Command::new("rename", WRITES | EXEC_ALLOWED, Handler::key_request),
+    Command::new("renamenx", WRITES | EXEC_ALLOWED, Handler::key_request),
+    Command::new(
+        "copy",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::key_request,
+    ),
+    Command::new("exists", EXEC_ALLOWED, Handler::key_request),
+    Command::new("type", EXEC_ALLOWED, Handler::key_request),
+    Command::new(
+        "hset",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::hash_request,
+    ),
+    Command::new("hget", EXEC_ALLOWED, Handler::hash_request),
+    Command::new("hdel", WRITES | EXEC_ALLOWED, Handler::hash_request),
+    Command::new("hgetall", EXEC_ALLOWED, Handler::hash_request),
+    Command::new("hkeys", EXEC_ALLOWED, Handler::hash_request),
+    Command::new("hlen", EXEC_ALLOWED, Handler::hash_request),
+    Command::new(
+        "lpush",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::list_request,
+    ),
+    Command::new(
+        "rpush",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::list_request,
+    ),
+    Command::new("lpop", WRITES | EXEC_ALLOWED, Handler::list_request),
+    Command::new("rpop", WRITES | EXEC_ALLOWED, Handler::list_request),
+    Command::new("lrange", EXEC_ALLOWED, Handler::list_request),
+    Command::new("llen", EXEC_ALLOWED, Handler::list_request),
+    Command::new("ltrim", WRITES | EXEC_ALLOWED, Handler::list_request),
+    Command::new(
+        "sadd",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::set_request,
+    ),
+    Command::new("srem", WRITES | EXEC_ALLOWED, Handler::set_request),
+    Command::new("smembers", EXEC_ALLOWED, Handler::set_request),
+    Command::new("sismember", EXEC_ALLOWED, Handler::set_request),
+    Command::new("scard", EXEC_ALLOWED, Handler::set_request),
+    Command::new(
+        "zadd",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::zset_request,
+    ),
+    Command::new("zrange", EXEC_ALLOWED, Handler::zset_request),
+    Command::new("zrank", EXEC_ALLOWED, Handler::zset_request),
+    Command::new("zscore", EXEC_ALLOWED, Handler::zset_request),
+    Command::new(
+        "zincrby",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::zset_request,
+    ),
+    Command::new("subscribe", 0, Handler::pubsub_request),
+    Command::new("unsubscribe", 0, Handler::pubsub_request),
+    Command::new("publish", 0, Handler::pubsub_request),
+    Command::new("watch", 0, Handler::watch_request),
+    Command::new("unwatch", 0, Handler::watch_request),
+    Command::new("watchers", 0, Handler::watch_request),
+    Command::new("version", EXEC_ALLOWED, Handler::key_request),
+    Command::new("getmeta", EXEC_ALLOWED, Handler::meta_request),
+    Command::new("history", EXEC_ALLOWED, Handler::meta_request),
+    Command::new(
+        "revert",
+        WRITES | GROWS_MEMORY | EXEC_ALLOWED,
+        Handler::meta_request,
+    ),
+    // each command inside exec and eval makes room for itself
+    Command::new("exec", WRITES, Handler::exec),
+    Command::new("eval", WRITES, Handler::eval),
+];
+
+#[derive(Debug, Default, Clone)]
+struct Database {
+    store: DataStore,
+    data_file: Option<String>,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Handler {
-    db: DataStore,
+    databases: BTreeMap<String, Database>,
+    /// the database each client selected and the unix time of its last request
+    selected: HashMap<SocketAddr, (String, u64)>,
+    current: String,
+    admin_keys: Vec<String>,
+    admins: HashSet<SocketAddr>,
+    max_payload: usize,
+    max_key_length: Option<usize>,
+    max_value_length: Option<usize>,
+    max_keys: Option<usize>,
+    subscriptions: Subscriptions,
+    watches: Subscriptions,
+    monitors: Subscriptions,
+    max_watchers: usize,
+    max_subscribers: usize,
+    outbox: Vec<(SocketAddr, String)>,
+    meta: Metadata,
+    script_limits: Limits,
+    memory: Memory,
+    config: Config,
+    started: u64,
+    last_save: u64,
+    stats: Stats,
+    slowlog: SlowLog,
+    log_values: bool,
     status: ServiceStatus,
+    snapshots: Option<Snapshots>,
 }
 
 impl Handler {
     /// create a new handler with the specified data store.
     pub fn new(db: DataStore) -> Handler {
-        Handler {
-            db,
+        let mut databases = BTreeMap::new();
+        databases.insert(
+            DEFAULT_DB.to_string(),
+            Database {
+                store: db,
+                data_file: None,
+            },
+        );
+
+        let mut handler = Handler {
+            databases,
+            selected: HashMap::new(),
+            current: DEFAULT_DB.to_string(),
+            admin_keys: vec![],
+            admins: HashSet::new(),
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            max_key_length: None,
+            max_value_length: None,
+            max_keys: None,
+            subscriptions: Subscriptions::create(DEFAULT_LEASE),
+            watches: Subscriptions::create(DEFAULT_LEASE),
+            monitors: Subscriptions::create(DEFAULT_LEASE),
+            max_watchers: DEFAULT_MAX_WATCHERS,
+            max_subscribers: DEFAULT_MAX_SUBSCRIBERS,
+            outbox: Vec::new(),
+            meta: Metadata::create(0),
+            script_limits: Limits::default(),
+            memory: Memory::create(0, Policy::NoEviction),
+            config: Config::default(),
+            started: get_ts(),
+            last_save: 0,
+            stats: Stats::default(),
+            slowlog: SlowLog::create(slowlog::DEFAULT_THRESHOLD_US, slowlog::DEFAULT_MAX_LEN),
+            log_values: false,
             status: ServiceStatus::create(),
+            snapshots: None,
+        };
+        handler.reset_versions();
+
+        handler
+    }
+
+    /// create a handler with the data store and the settings from config
+    pub fn create(db: DataStore, config: &Config) -> Handler {
+        let mut handler = Handler::new(db);
+        handler.config = config.copy();
+        handler.max_payload = config.max_payload();
+        handler.subscriptions = Subscriptions::create(config.pubsub_lease.unwrap_or(DEFAULT_LEASE));
+        handler.watches = Subscriptions::create(config.pubsub_lease.unwrap_or(DEFAULT_LEASE));
+        handler.monitors = Subscriptions::create(config.pubsub_lease.unwrap_or(DEFAULT_LEASE));
+        handler.meta = Metadata::create(config.key_history.unwrap_or(0));
+        handler.configure(config);
+        if let Err(response) = handler.check_store(handler.db(), true) {
+            warn!(
+                "data is over the limits, not loaded: {}",
+                response.as_string()
+            );
+            handler.replace_store(DataStore::create());
+        }
+        handler.reset_versions();
+
+        if let Some(default) = handler.databases.get_mut(DEFAULT_DB) {
+            default.data_file = config.data_file.clone();
+        }
+
+        for (name, filename) in config.databases.iter().flatten() {
+            let mut store = DataStore::create();
+            match store.loaddb(filename) {
+                Ok(sz) => info!("database {} loaded {} elements from {}", name, sz, filename),
+                Err(e) => warn!("database {} could not load {}: {}", name, filename, e),
+            }
+            if let Err(response) = handler.check_store(&store, true) {
+                warn!(
+                    "database {} is over the limits, not loaded: {}",
+                    name,
+                    response.as_string()
+                );
+                store = DataStore::create();
+            }
+
+            let database = Database {
+                store,
+                data_file: Some(filename.to_string()),
+            };
+            handler.databases.insert(name.to_string(), database);
+            handler.current = name.to_string();
+            handler.reset_versions();
+        }
+        handler.current = DEFAULT_DB.to_string();
+
+        handler
+    }
+
+    /// apply the settings that can change while running: auth keys, limits, slowlog, memory,
+    /// scripts, log values and the snapshot policy
+    fn configure(&mut self, config: &Config) {
+        self.snapshots = config.snapshots.as_ref().map(Snapshots::create);
+        self.admin_keys = config.admin_keys.clone().unwrap_or_default();
+        self.max_key_length = config.max_key_length;
+        self.max_value_length = config.max_value_length;
+        self.max_keys = config.max_keys;
+        self.log_values = config.log_values.unwrap_or(false);
+        self.max_watchers = config.max_watchers.unwrap_or(DEFAULT_MAX_WATCHERS);
+        self.max_subscribers = config.max_subscribers.unwrap_or(DEFAULT_MAX_SUBSCRIBERS);
+        self.slowlog.configure(
+            config
+                .slowlog_threshold_us
+                .unwrap_or(slowlog::DEFAULT_THRESHOLD_US),
+            config.slowlog_max_len.unwrap_or(slowlog::DEFAULT_MAX_LEN),
+        );
+        let policy = match config.eviction_policy.as_deref().map(str::parse) {
+            Some(Ok(policy)) => policy,
+            Some(Err(e)) => {
+                warn!("{}, using noeviction", e);
+                Policy::NoEviction
+            }
+            None => Policy::NoEviction,
+        };
+        self.memory
+            .configure(config.max_memory.unwrap_or(0), policy);
+        self.script_limits = Limits {
+            max_steps: config.script_max_steps.unwrap_or(script::DEFAULT_MAX_STEPS),
+            timeout: Duration::from_millis(
+                config
+                    .script_timeout_ms
+                    .unwrap_or(script::DEFAULT_TIMEOUT_MS),
+            ),
+            max_text: config.max_value_length.unwrap_or(script::DEFAULT_MAX_TEXT),
+        };
+    }
+
+    /// read the config file again and apply the settings that can change while running; returns
+    /// the changed settings that were applied and those that need a restart
+    pub fn reload_config(&mut self) -> Result<(Vec<String>, Vec<String>)> {
+        let mut config = self.config.reload()?;
+        let (problems, warnings): (Vec<_>, Vec<_>) = config
+            .problems()
+            .into_iter()
+            .map(|(setting, problem)| (Config::reloadable(&setting), problem))
+            .partition(|(reloadable, _)| *reloadable);
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems.into_iter().map(|(_, problem)| problem).collect();
+            return Err(anyhow!("{}", problems.join("; ")));
+        }
+        for (_, problem) in warnings {
+            warn!("config reload: {}", problem);
+        }
+
+        let (restart, reloaded): (Vec<String>, Vec<String>) = self
+            .config
+            .changes(&config)?
+            .into_iter()
+            .partition(|key| RESTART_KEYS.contains(&key.as_str()));
+
+        if let Err(e) = config.reload_logger() {
+            warn!("log config not reloaded: {}", e);
         }
+        if config.admin_keys != self.config.admin_keys {
+            // clients authenticated with a removed key must auth again
+            self.admins.clear();
+        }
+
+        config.keep_restart_settings(&self.config);
+        self.configure(&config);
+        self.config = config;
+        info!(
+            "config reloaded: {:?}, restart needed for: {:?}",
+            reloaded, restart
+        );
+
+        Ok((reloaded, restart))
+    }
+
+    /// true if request params and replies are logged
+    pub fn log_values(&self) -> bool {
+        self.log_values
     }
 
     /// returns a response to the request, including error responses
     pub fn handle_request(&mut self, request: Request) -> Response {
+        self.dispatch(None, request)
+    }
+
+    /// returns a response to the request from the client address; used for per-client state
+    pub fn handle_client_request(&mut self, addr: SocketAddr, request: Request) -> Response {
+        self.dispatch(Some(addr), request)
+    }
+
+    /// write the request, latency, keyspace, memory and snapshot metrics
+    pub fn write_metrics(&self, out: &mut Exposition) {
+        out.metric(
+            "udp_requests_total",
+            "counter",
+            "requests by command and status code",
+        );
+        for (cmd, stats) in self.stats.iter() {
+            for (code, count) in stats.codes.iter() {
+                let code = code.to_string();
+                out.sample(
+                    "udp_requests_total",
+                    &[("command", cmd), ("code", &code)],
+                    count,
+                );
+            }
+        }
+
+        let name = "udp_request_duration_seconds";
+        out.metric(name, "histogram", "request handling latency by command");
+        for (cmd, stats) in self.stats.iter() {
+            let bucket = format!("{}_bucket", name);
+            for (micros, count) in stats.latency.buckets() {
+                let le = (micros as f64 / 1_000_000.0).to_string();
+                out.sample(&bucket, &[("command", cmd), ("le", &le)], count);
+            }
+            let count = stats.latency.count();
+            let sum = stats.latency.sum_micros() as f64 / 1_000_000.0;
+            out.sample(&bucket, &[("command", cmd), ("le", "+Inf")], count);
+            out.sample(&format!("{}_sum", name), &[("command", cmd)], sum);
+            out.sample(&format!("{}_count", name), &[("command", cmd)], count);
+        }
+
+        out.metric("udp_keys", "gauge", "the number of keys in each database");
+        for (name, db) in self.databases.iter() {
+            out.sample("udp_keys", &[("db", name)], db.store.dbsize());
+        }
+
+        out.metric("udp_memory_used_bytes", "gauge", "approximate bytes used");
+        out.sample("udp_memory_used_bytes", &[], self.memory.used());
+        out.metric(
+            "udp_memory_max_bytes",
+            "gauge",
+            "max_memory; zero is unlimited",
+        );
+        out.sample("udp_memory_max_bytes", &[], self.memory.max_memory());
+        out.metric("udp_evicted_keys_total", "counter", "keys evicted");
+        out.sample("udp_evicted_keys_total", &[], self.memory.evicted());
+        out.metric("udp_rejected_writes_total", "counter", "writes refused");
+        out.sample("udp_rejected_writes_total", &[], self.memory.rejected());
+
+        out.metric(
+            "udp_snapshots_enabled",
+            "gauge",
+            "1 if snapshots are configured",
+        );
+        out.sample(
+            "udp_snapshots_enabled",
+            &[],
+            u8::from(self.snapshots.is_some()),
+        );
+        out.metric(
+            "udp_last_save_timestamp_seconds",
+            "gauge",
+            "the last savedb; zero is never",
+        );
+        out.sample("udp_last_save_timestamp_seconds", &[], self.last_save);
+        out.metric(
+            "udp_start_time_seconds",
+            "gauge",
+            "the unix time the handler started",
+        );
+        out.sample("udp_start_time_seconds", &[], self.started);
+    }
+
+    /// add the request, with its values redacted unless log_values is set, to the slowlog if it
+    /// took longer than the threshold to handle
+    pub fn record_slow(&mut self, client: Option<SocketAddr>, msg: &str, elapsed: Duration) {
+        let msg = trace::redact(msg, self.log_values);
+        if self.slowlog.record(client, &msg, elapsed) {
+            warn!("slow request: {:?} {}us", msg, elapsed.as_micros());
+        }
+    }
+
+    /// true if the command changes data; the command may have a db: prefix
+    pub fn is_mutating(cmd: &str) -> bool {
+        let cmd = cmd.rsplit(':').next().unwrap_or(cmd);
+        Command::find(cmd).is_some_and(Command::writes)
+    }
+
+    /// remove and return the messages to push to other clients, e.g. published messages
+    pub fn drain_messages(&mut self) -> Vec<(SocketAddr, String)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// pick the database from a db: prefix or the client selection, then run the command
+    fn dispatch(&mut self, client: Option<SocketAddr>, request: Request) -> Response {
         self.status.access.incr();
         info!("handle request: {}", &request.cmd);
 
+        let (name, request) = match request.cmd.split_once(':') {
+            Some((name, cmd)) => (
+                name.to_string(),
+                Request {
+                    cmd: cmd.to_string(),
+                    params: request.params,
+                },
+            ),
+            None => (self.selected_database(client), request),
+        };
+
+        if !self.databases.contains_key(&name) {
+            self.status.errors.incr();
+            return Response::create(Status::not_found(), name);
+        }
+        self.current = name;
+        self.feed_monitors(client, &request);
+
+        let command = Command::find(&request.cmd);
+        let name = command.map_or("unknown", |command| command.name);
+
+        let started = Instant::now();
+        let response = self.execute_checked(client, request);
+        self.stats
+            .record(name, response.status.code, started.elapsed());
+
+        response
+    }
+
+    /// run the command, first making room for it if it needs memory; out-of-memory if there
+    /// is no room, e.g. with noeviction
+    fn execute_checked(&mut self, client: Option<SocketAddr>, request: Request) -> Response {
+        let grows_memory = Command::find(&request.cmd).is_some_and(Command::grows_memory);
+        if grows_memory && !self.make_room(write_size(&request)) {
+            self.memory.reject();
+            warn!("max memory reached, refused {}", request.cmd);
+            return Response::create(Status::out_of_memory(), request.cmd);
+        }
+
+        self.execute(client, request)
+    }
+
+    /// evict keys until the incoming bytes fit under max_memory; false if the policy doesn't
+    /// evict or the write could never fit
+    fn make_room(&mut self, incoming: usize) -> bool {
+        if self.memory.max_memory() > 0 && incoming > self.memory.max_memory() {
+            return false;
+        }
+
+        while !self.memory.fits(incoming) {
+            if !self.memory.can_evict() {
+                return false;
+            }
+
+            let Some((db, key)) = self.memory.victim() else {
+                return false;
+            };
+            info!("evicting {} from {}", key, db);
+            let current = std::mem::replace(&mut self.current, db);
+            let previous = self.db().remove(&key);
+            self.key_changed("evict", &key, previous);
+            self.current = current;
+        }
+
+        true
+    }
+
+    /// run the command against the current database
+    fn execute(&mut self, client: Option<SocketAddr>, request: Request) -> Response {
+        for key in changed_keys(&request) {
+            self.memory.touch(&self.current, key);
+        }
+
+        match Command::find(&request.cmd) {
+            Some(command) => (command.run)(self, client, &request),
+            None => {
+                self.status.errors.incr();
+                error!("bad request: {}", &request.cmd);
+                Response::create(Status::bad_request(), request.cmd.to_string())
+            }
+        }
+    }
+
+    /// the server commands: ping, now, now_ns, status, info, stats and auth
+    fn server_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let param = request.params[0].as_str();
         match request.cmd.as_str() {
             "ping" => Response::create_ok("PONG".to_string()),
             "now" => Response::create_ok(format!("{}", get_ts())),
             "now_ns" => Response::create_ok(format!("{}", get_ns())),
-            "status" => Response::create_ok(format!("{}", self.status)),
+            "status" => Response::create_ok(format!(
+                "{}, used_memory: {}, max_memory: {}, evicted_keys: {}, rejected_writes: {}",
+                self.status,
+                self.memory.used(),
+                self.memory.max_memory(),
+                self.memory.evicted(),
+                self.memory.rejected()
+            )),
+            "info" => self.info(param),
+            "stats" if param == "reset" => {
+                if !self.is_admin(client) {
+                    return Response::create(Status::forbidden(), request.cmd.to_string());
+                }
+                self.stats.reset();
+                Response::create_ok("ok".to_string())
+            }
+            "stats" => self.command_stats(param),
+            _ => self.auth(client, param),
+        }
+    }
+
+    /// the string commands: get, set and del
+    fn string_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let key = request.params[0].as_str();
+        match request.cmd.as_str() {
             "get" => {
                 info!("get {:?}", &request.params);
-                let key = request.params[0].as_str();
                 self.get(key)
             }
             "set" => {
-                info!("set {:?}", &request.params);
+                let msg = format!("set {}", request.params.join(" "));
+                info!("{}", trace::redact(&msg, self.log_values));
                 if request.params.len() == 2 {
-                    let key = request.params[0].as_str();
                     let value = request.params[1].clone().into_bytes();
                     self.set(key, value)
                 } else {
                     Response::create(Status::bad_request(), request.cmd.to_string())
                 }
             }
-            "del" => {
+            _ => {
                 info!("del {:?}", &request.params);
-                let key = request.params[0].as_str();
                 self.del(key)
             }
-            "dbsize" => {
-                let sz = self.dbsize();
-                Response::create_ok(sz.to_string())
+        }
+    }
+
+    /// the key commands: exists, type, version, rename, renamenx and copy
+    fn key_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let args = request.args();
+        match request.cmd.as_str() {
+            "exists" => {
+                let count = args
+                    .iter()
+                    .filter(|key| self.db().get(key).is_some())
+                    .count();
+                Response::create_ok(count.to_string())
             }
-            "keys" => {
-                let keys = self.db.keys();
-                let body = format!("{:?}", keys);
-                Response::create_ok(body)
+            "type" => match self.db().get(&request.params[0]) {
+                Some(value) => Response::create_ok(Value::decode(value).type_name().to_string()),
+                None => Response::create_ok("none".to_string()),
+            },
+            "version" => Response::create_ok(self.version(&request.params[0]).to_string()),
+            _ if args.len() != 2 => {
+                Response::create(Status::bad_request(), request.cmd.to_string())
             }
-            "loaddb" => {
-                let filename = request.params[0].as_str();
-                if let Ok(sz) = self.db.loaddb(filename) {
-                    Response::create_ok(sz.to_string())
-                } else {
-                    Response::create(Status::bad_request(), filename.to_string())
+            "rename" => self.rename(args[0], args[1], true),
+            "renamenx" => self.rename(args[0], args[1], false),
+            _ => self.copy(args[0], args[1]),
+        }
+    }
+
+    /// the database commands: dbsize, keys, databases, select and flushdb
+    fn database_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        match request.cmd.as_str() {
+            "dbsize" => Response::create_ok(self.db().dbsize().to_string()),
+            "keys" => Response::create_ok(format!("{:?}", self.db().keys())),
+            "databases" => {
+                let list: Vec<String> = self
+                    .databases
+                    .iter()
+                    .map(|(name, db)| format!("{}:{}", name, db.store.dbsize()))
+                    .collect();
+                Response::create_ok(format!("{:?}", list))
+            }
+            "select" => self.select(client, request.params[0].as_str()),
+            _ => {
+                if !self.is_admin(client) {
+                    return Response::create(Status::forbidden(), request.cmd.to_string());
                 }
+                self.flushdb()
             }
-            "savedb" => {
-                let filename = request.params[0].as_str();
-                if let Ok(sz) = self.db.savedb(filename) {
-                    Response::create_ok(sz.to_string())
-                } else {
-                    Response::create(Status::bad_request(), filename.to_string())
+        }
+    }
+
+    /// the persistence commands: loaddb, savedb, listsnapshots and restore
+    fn persistence_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let param = request.params[0].as_str();
+        match request.cmd.as_str() {
+            "loaddb" => {
+                let args = request.args();
+                match args.first() {
+                    Some(&"--replace") => {
+                        if !self.is_admin(client) {
+                            return Response::create(Status::forbidden(), request.cmd.to_string());
+                        }
+                        self.loaddb_replace(args.get(1).unwrap_or(&""))
+                    }
+                    _ => self.loaddb(param),
                 }
             }
+            "savedb" => self.savedb(param),
+            "listsnapshots" => self.list_snapshots(),
             _ => {
-                self.status.errors.incr();
-                error!("bad request: {}", &request.cmd);
-                Response::create(Status::bad_request(), request.cmd.to_string())
+                if !self.is_admin(client) {
+                    return Response::create(Status::forbidden(), request.cmd.to_string());
+                }
+                self.restore(param)
             }
         }
     }
 
-    /// get the item from key
-    fn get(&self, key: &str) -> Response {
-        match self.db.get(key) {
-            Some(value) => {
-                let body = String::from_utf8(value).unwrap();
-                Response::create_ok(body)
-            }
-            _ => Response::create(Status::not_found(), key.to_string()),
-        }
+    /// the data store for the current request
+    fn db(&self) -> &DataStore {
+        &self.database().store
     }
 
-    /// set the value from key
-    fn set(&mut self, key: &str, value: Vec<u8>) -> Response {
-        if let Some(value) = self.db.set(key, value) {
-            let val = String::from_utf8(value).unwrap();
-            // queue change for replication/backup
-            Response::create_ok(val)
-        } else {
-            Response::create_ok("ok".to_string())
-        }
+    /// the current database
+    fn database(&self) -> &Database {
+        self.databases
+            .get(&self.current)
+            .expect("the current database should exist")
     }
 
-    fn del(&mut self, key: &str) -> Response {
-        if let Some(value) = self.db.remove(key) {
-            let val = String::from_utf8(value).unwrap();
-            // queue change for replication/backup
-            Response::create_ok(val)
+    /// return the section, or all of the sections that fit in a datagram, as a json object
+    fn info(&mut self, section: &str) -> Response {
+        let sections = if section.is_empty() {
+            INFO_SECTIONS.to_vec()
+        } else if INFO_SECTIONS.contains(&section) {
+            vec![section]
         } else {
-            Response::create_ok("ok".to_string())
+            return Response::create(Status::bad_request(), section.to_string());
+        };
+
+        let mut info = serde_json::Map::new();
+        let mut status = Status::ok();
+        for name in sections {
+            let value = self.info_section(name);
+            info.insert(name.to_string(), value);
+
+            // the status header plus the json
+            let size = 16 + serde_json::Value::Object(info.clone()).to_string().len();
+            if size > self.max_payload && info.len() > 1 {
+                info.remove(name);
+                status = Status::partial();
+                break;
+            }
         }
-    }
 
-    /// return the number of elements in the k/v map
-    pub fn dbsize(&self) -> usize {
-        self.db.dbsize()
+        Response::create(status, serde_json::Value::Object(info).to_string())
     }
-}
 
-/// return the unix timestamp
-fn get_ts() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("seconds")
-        .as_secs()
-}
+    /// return the counters and latency percentiles for the command, or for all of the commands
+    /// that fit in a datagram, as a json object
+    fn command_stats(&self, cmd: &str) -> Response {
+        let commands = if cmd.is_empty() {
+            self.stats.commands()
+        } else {
+            vec![cmd]
+        };
 
-/// return the current time in nanoseconds
-fn get_ns() -> u128 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("nanos")
-        .as_nanos()
-}
+        let mut stats = serde_json::Map::new();
+        let mut status = Status::ok();
+        for name in commands {
+            let Some(summary) = self.stats.summary(name) else {
+                return Response::create(Status::not_found(), name.to_string());
+            };
+            stats.insert(name.to_string(), serde_json::json!(summary));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            let size = 16 + serde_json::Value::Object(stats.clone()).to_string().len();
+            if size > self.max_payload && stats.len() > 1 {
+                stats.remove(name);
+                status = Status::partial();
+                break;
+            }
+        }
 
-    fn create_handler() -> Handler {
-        let db = DataStore::create();
-        Handler::new(db)
+        Response::create(status, serde_json::Value::Object(stats).to_string())
     }
 
-    #[test]
-    fn get_set_del_dbsize() {
-        let mut handler = create_handler();
-        assert_eq!(handler.db.dbsize(), 0);
+    /// get or set a setting, reload the config file or write the settings back to it
+    fn config_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let args = request.args();
+        if !matches!(
+            args.as_slice(),
+            ["reload"] | ["rewrite"] | ["get", _] | ["set", _, _]
+        ) {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
 
-        let key = "1234.MyKey";
-        let value = "This is a test value";
-        let msg = format!("set {} {}", key, value);
-        let request = Request::from_message(msg.as_str()).unwrap();
-        let response = handler.handle_request(request.clone());
-        info!("{:?}", response);
-        assert_eq!(handler.db.dbsize(), 1);
+        if !self.is_admin(client) {
+            return Response::create(Status::forbidden(), request.cmd.to_string());
+        }
 
-        let response = handler.handle_request(request.clone());
-        info!("{:?}", response);
-        assert_eq!(handler.db.dbsize(), 1);
+        let result = match args.as_slice() {
+            ["get", name] => match self.config.get(name) {
+                Ok(Some(value)) => Ok(value),
+                Ok(None) => return Response::create(Status::not_found(), name.to_string()),
+                Err(e) => Err(e),
+            },
+            ["set", name, value] => self.set_config(name, value).map(|_| "ok".to_string()),
+            ["rewrite"] => self.config.rewrite(),
+            _ => self.reload_config().map(|(reloaded, restart)| {
+                serde_json::json!({ "reloaded": reloaded, "restart": restart }).to_string()
+            }),
+        };
+
+        match result {
+            Ok(body) => Response::create_ok(body),
+            Err(e) => Response::create(Status::bad_request(), e.to_string()),
+        }
+    }
+
+    /// change a setting and apply it
+    fn set_config(&mut self, name: &str, value: &str) -> Result<()> {
+        let mut config = self.config.copy();
+        config.set(name, value)?;
+        if name == "log_level" {
+            if let Err(e) = config.reload_logger() {
+                warn!("log level not changed: {}", e);
+            }
+        }
+
+        self.configure(&config);
+        self.config = config;
+        info!("config set {} {}", name, value);
+
+        Ok(())
+    }
+
+    /// register the client to be sent every request until the lease expires, or remove it
+    fn monitor_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let Some(addr) = client else {
+            return Response::create(
+                Status::bad_request(),
+                "monitor requires a client".to_string(),
+            );
+        };
+
+        if request.cmd == "unmonitor" {
+            let removed = self.monitors.unsubscribe(MONITOR_TOPIC, addr);
+            return Response::create_ok(if removed { "1" } else { "0" }.to_string());
+        }
+
+        if !self.is_admin(client) {
+            return Response::create(Status::forbidden(), request.cmd.to_string());
+        }
+
+        let lease = self.monitors.subscribe(MONITOR_TOPIC, addr);
+        info!("client {} monitoring", addr);
+        Response::create_ok(lease.to_string())
+    }
+
+    /// push the request, with its values redacted unless log_values is set, to the monitoring
+    /// clients, e.g. monitor:1700000000.123456 [default 127.0.0.1:53211] get k1
+    fn feed_monitors(&mut self, client: Option<SocketAddr>, request: &Request) {
+        if self.monitors.is_empty() {
+            return;
+        }
+
+        let monitors = self.monitors.subscribers(|_| true);
+        if monitors.is_empty() {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let source = client.map_or("local".to_string(), |addr| addr.to_string());
+        let msg = format!("{} {}", request.cmd, request.params.join(" "));
+        let mut message = format!(
+            "monitor:{}.{:06} [{} {}] {}",
+            now.as_secs(),
+            now.subsec_micros(),
+            self.current,
+            source,
+            trace::redact(msg.trim(), self.log_values)
+        );
+        if message.len() > self.max_payload {
+            let mut end = self.max_payload;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+
+        for addr in monitors {
+            self.outbox.push((addr, message.trim_end().to_string()));
+        }
+    }
+
+    /// show the newest slow requests, the number held, or clear them
+    fn slowlog_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let args = request.args();
+        match args.as_slice() {
+            ["get"] | ["get", _] => {
+                // the entries name the clients and their requests
+                if !self.is_admin(client) {
+                    return Response::create(Status::forbidden(), request.cmd.to_string());
+                }
+                let n = match args.get(1).map(|n| parsers::as_number::<usize>(n)) {
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Response::create(Status::bad_request(), args.join(" ")),
+                    None => slowlog::DEFAULT_MAX_LEN,
+                };
+
+                let mut entries = Vec::new();
+                for entry in self.slowlog.get(n) {
+                    entries.push(entry);
+                    let size = 16 + serde_json::to_string(&entries).unwrap_or_default().len();
+                    if size > self.max_payload && entries.len() > 1 {
+                        entries.pop();
+                        let body = serde_json::to_string(&entries).unwrap_or_default();
+                        return Response::create(Status::partial(), body);
+                    }
+                }
+                Response::create_ok(serde_json::to_string(&entries).unwrap_or_default())
+            }
+            ["len"] => Response::create_ok(self.slowlog.len().to_string()),
+            ["reset"] => {
+                if !self.is_admin(client) {
+                    return Response::create(Status::forbidden(), request.cmd.to_string());
+                }
+                self.slowlog.reset();
+                Response::create_ok("ok".to_string())
+            }
+            _ => Response::create(Status::bad_request(), request.cmd.to_string()),
+        }
+    }
+
+    /// the values for one info section
+    fn info_section(&mut self, name: &str) -> serde_json::Value {
+        use serde_json::json;
+
+        match name {
+            "server" => json!({
+                "name": self.config.name,
+                "version": crate::VERSION,
+                "pid": std::process::id(),
+                "config_file": self.config.config_file,
+                "bind": format!("{}:{}", self.config.host, self.config.port),
+                "started": self.started,
+                "uptime_seconds": get_ts().saturating_sub(self.started),
+            }),
+            "clients" => {
+                self.subscriptions.expire();
+                self.watches.expire();
+                json!({
+                    "selected": self.selected.len(),
+                    "admins": self.admins.len(),
+                    "subscriptions": self.subscriptions.len(),
+                    "watchers": self.watches.len(),
+                })
+            }
+            "memory" => json!({
+                "used_memory": self.memory.used(),
+                "max_memory": self.memory.max_memory(),
+                "eviction_policy": self.memory.policy().to_string(),
+                "evicted_keys": self.memory.evicted(),
+                "rejected_writes": self.memory.rejected(),
+            }),
+            "persistence" => {
+                let files: BTreeMap<&String, &Option<String>> = self
+                    .databases
+                    .iter()
+                    .map(|(name, db)| (name, &db.data_file))
+                    .collect();
+                json!({
+                    "data_files": files,
+                    "snapshots": self.config.snapshots.as_ref().map(|s| &s.folder),
+                    "last_save": self.last_save,
+                })
+            }
+            "keyspace" => {
+                let keyspace: serde_json::Map<String, serde_json::Value> = self
+                    .databases
+                    .iter()
+                    .map(|(name, db)| {
+                        // keys don't expire, so there are no expiring keys to count
+                        let value = json!({ "keys": db.store.dbsize(), "expires": 0 });
+                        (name.to_string(), value)
+                    })
+                    .collect();
+                serde_json::Value::Object(keyspace)
+            }
+            _ => json!(self.stats.calls()),
+        }
+    }
+
+    /// the database the client selected, refreshing the selection; the default if none
+    fn selected_database(&mut self, client: Option<SocketAddr>) -> String {
+        let now = get_ts();
+        match client.and_then(|addr| self.selected.get_mut(&addr)) {
+            Some((name, used)) if now.saturating_sub(*used) <= SELECTION_IDLE => {
+                *used = now;
+                name.to_string()
+            }
+            _ => DEFAULT_DB.to_string(),
+        }
+    }
+
+    /// set the database used by the client's requests
+    fn select(&mut self, client: Option<SocketAddr>, name: &str) -> Response {
+        let Some(addr) = client else {
+            return Response::create(
+                Status::bad_request(),
+                "select requires a client".to_string(),
+            );
+        };
+
+        if !self.databases.contains_key(name) {
+            return Response::create(Status::not_found(), name.to_string());
+        }
+
+        // drop the selections of clients that have gone away
+        let now = get_ts();
+        self.selected
+            .retain(|_, (_, used)| now.saturating_sub(*used) <= SELECTION_IDLE);
+
+        info!("client {} selected database {}", addr, name);
+        if name == DEFAULT_DB {
+            self.selected.remove(&addr);
+        } else {
+            self.selected.insert(addr, (name.to_string(), now));
+        }
+        Response::create_ok(name.to_string())
+    }
+
+    /// mark the client as an admin if the key matches one of the configured admin keys
+    fn auth(&mut self, client: Option<SocketAddr>, key: &str) -> Response {
+        let Some(addr) = client else {
+            return Response::create(Status::bad_request(), "auth requires a client".to_string());
+        };
+
+        if !key.is_empty() && self.admin_keys.iter().any(|k| k == key) {
+            info!("client {} authenticated as admin", addr);
+            self.admins.insert(addr);
+            Response::create_ok("ok".to_string())
+        } else {
+            warn!("client {} failed admin auth", addr);
+            self.admins.remove(&addr);
+            Response::create(Status::forbidden(), "auth".to_string())
+        }
+    }
+
+    /// local requests are trusted; clients must auth with an admin key
+    fn is_admin(&self, client: Option<SocketAddr>) -> bool {
+        client.map_or(true, |addr| self.admins.contains(&addr))
+    }
+
+    /// remove all the keys from the current database
+    fn flushdb(&mut self) -> Response {
+        let size = self.db().dbsize();
+        let before = self.replace_store(DataStore::create());
+        self.reset_versions();
+        self.bulk_changed("flushdb", &before);
+
+        info!("flushed {} elements from {}", size, self.current);
+        Response::create_ok(size.to_string())
+    }
+
+    /// move the value to the new key; when overwrite is false an existing new key is kept
+    fn rename(&mut self, old: &str, new: &str, overwrite: bool) -> Response {
+        let Some(value) = self.db().get(old) else {
+            return Response::create(Status::not_found(), old.to_string());
+        };
+
+        if !overwrite && self.db().get(new).is_some() {
+            return Response::create_ok("0".to_string());
+        }
+
+        if old != new {
+            if let Err(response) = self.check_size(new, value.len(), false) {
+                return response;
+            }
+            let previous = self.db().set(new, value);
+            let removed = self.db().remove(old);
+            self.key_changed("del", old, removed);
+            self.key_changed("set", new, previous);
+        }
+
+        Response::create_ok("1".to_string())
+    }
+
+    /// copy the value to the destination key unless the destination exists
+    fn copy(&mut self, src: &str, dst: &str) -> Response {
+        let Some(value) = self.db().get(src) else {
+            return Response::create(Status::not_found(), src.to_string());
+        };
+
+        if self.db().get(dst).is_some() {
+            return Response::create_ok("0".to_string());
+        }
+
+        if let Err(response) = self.check_size(dst, value.len(), true) {
+            return response;
+        }
+        self.db().set(dst, value);
+        self.key_changed("set", dst, None);
+        Response::create_ok("1".to_string())
+    }
+
+    /// register or remove the client's watch on a key, or a prefix ending with *, in the current database
+    fn watch_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        if request.cmd == "watchers" {
+            if !self.is_admin(client) {
+                return Response::create(Status::forbidden(), request.cmd.to_string());
+            }
+            self.watches.expire();
+            return Response::create_ok(format!("{:?}", self.watches.list()));
+        }
+
+        let pattern = request.params[0].as_str();
+        let Some(addr) = client else {
+            return Response::create(Status::bad_request(), "watch requires a client".to_string());
+        };
+        if pattern.is_empty() {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
+
+        let topic = format!("{}:{}", self.current, pattern);
+        if request.cmd == "unwatch" {
+            let removed = self.watches.unsubscribe(&topic, addr);
+            return Response::create_ok(if removed { "1" } else { "0" }.to_string());
+        }
+
+        // expired watches don't count against the limit
+        self.watches.expire();
+        let renewal = self.watches.subscribers(|t| t == topic).contains(&addr);
+        if !renewal && self.watches.len() >= self.max_watchers {
+            warn!("max watchers reached, refused {} for {}", pattern, addr);
+            return Response::create(Status::too_many(), pattern.to_string());
+        }
+
+        let lease = self.watches.subscribe(&topic, addr);
+        info!("client {} watching {}", addr, topic);
+        Response::create_ok(lease.to_string())
+    }
+
+    /// the version of the key in the current database; zero if the key does not exist
+    fn version(&self, key: &str) -> u64 {
+        self.meta.version(&self.current, key)
+    }
+
+    /// show the key's version and modified time, list its prior values, or revert to one
+    fn meta_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let args = request.args();
+        let Some(key) = args.first().copied() else {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        };
+
+        let meta = self
+            .meta
+            .get(&self.current, key)
+            .cloned()
+            .unwrap_or_default();
+        match request.cmd.as_str() {
+            "getmeta" => {
+                let Some(value) = self.db().get(key) else {
+                    return Response::create(Status::not_found(), key.to_string());
+                };
+                let body = serde_json::json!({
+                    "version": meta.version,
+                    "modified": meta.modified,
+                    "type": Value::decode(value.clone()).type_name(),
+                    "size": value.len(),
+                    "history": meta.history.len(),
+                });
+                Response::create_ok(body.to_string())
+            }
+            "history" => {
+                let count = match args.get(1).map(|n| parsers::as_number::<usize>(n)) {
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        return Response::create(Status::bad_request(), key.to_string())
+                    }
+                    None => meta.history.len(),
+                };
+                let Some(start) = Handler::page_start(args.get(2).unwrap_or(&"")) else {
+                    return Response::create(Status::bad_request(), key.to_string());
+                };
+                let entries: Vec<String> = meta
+                    .history
+                    .iter()
+                    .take(count)
+                    .map(|entry| serde_json::to_string(entry).unwrap_or_default())
+                    .collect();
+                self.json_page(&entries, start, ('[', ']'))
+            }
+            _ => {
+                let Some(Ok(version)) = args.get(1).map(|v| parsers::as_number::<u64>(v)) else {
+                    return Response::create(Status::bad_request(), key.to_string());
+                };
+                let Some(entry) = meta.history.iter().find(|h| h.version == version) else {
+                    return Response::create(Status::not_found(), format!("{} {}", key, version));
+                };
+
+                // a reverted value may be typed so it bypasses the set command's prefix check
+                let is_new = self.db().get(key).is_none();
+                if let Err(response) = self.check_size(key, entry.value.len(), is_new) {
+                    return response;
+                }
+                let previous = self.db().set(key, entry.value.clone());
+                self.key_changed("set", key, previous);
+                Response::create_ok(self.version(key).to_string())
+            }
+        }
+    }
+
+    /// swap the store of the current database and return the one replaced
+    fn replace_store(&mut self, store: DataStore) -> DataStore {
+        match self.databases.get_mut(&self.current) {
+            Some(database) => std::mem::replace(&mut database.store, store),
+            None => DataStore::create(),
+        }
+    }
+
+    /// give every key in the current database a new version and recount its memory after a
+    /// bulk load, restore or flush
+    fn reset_versions(&mut self) {
+        let keys = self.db().keys();
+        let sizes = keys
+            .iter()
+            .map(|key| (key.to_string(), self.db().get(key).map_or(0, |v| v.len())))
+            .collect();
+        self.meta.reset(&self.current, keys);
+        self.memory.reset(&self.current, sizes);
+    }
+
+    /// run the sub-commands of a single datagram in order, all or none; the list is json,
+    /// e.g. exec [["ifversion","k1","3"],["set","k1","one"],["get","k1"]]
+    fn exec(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let raw = request.params.join(" ");
+        let commands: Vec<Vec<String>> = match serde_json::from_str(&raw) {
+            Ok(commands) => commands,
+            Err(e) => return Response::create(Status::bad_request(), e.to_string()),
+        };
+
+        // the optimistic checks run before anything changes
+        for command in commands
+            .iter()
+            .filter(|c| c.first().is_some_and(|c| c == "ifversion"))
+        {
+            let expected = command.get(2).and_then(|v| v.parse::<u64>().ok());
+            match (command.get(1), expected) {
+                (Some(key), Some(expected)) if self.version(key) == expected => (),
+                (Some(key), Some(_)) => {
+                    return Response::create(Status::precondition_failed(), key.to_string())
+                }
+                _ => return Response::create(Status::bad_request(), "ifversion".to_string()),
+            }
+        }
+
+        let outbox_len = self.outbox.len();
+        let mut undo: Vec<Undo> = Vec::new();
+        let mut responses: Vec<String> = Vec::new();
+        for command in commands
+            .iter()
+            .filter(|c| c.first().is_some_and(|c| c != "ifversion"))
+        {
+            let response = match Request::from_parts(&command[0], &command[1..]) {
+                Ok(sub) if Command::find(&sub.cmd).is_some_and(Command::exec_allowed) => {
+                    self.save_undo(&sub, &mut undo);
+                    self.execute_checked(client, sub)
+                }
+                Ok(sub) => Response::create(Status::bad_request(), sub.cmd),
+                Err(e) => Response::create(Status::bad_request(), e.to_string()),
+            };
+
+            let failed = response.status.code >= 400 && response.status.code != 404;
+            responses.push(response.as_string());
+            if failed {
+                warn!("exec aborted at {}", command[0]);
+                self.rollback(undo);
+                self.outbox.truncate(outbox_len);
+                let body = serde_json::to_string(&responses).unwrap_or_default();
+                return Response::create(response.status, body);
+            }
+        }
+
+        Response::create_ok(serde_json::to_string(&responses).unwrap_or_default())
+    }
+
+    /// run the script; its commands are the ones allowed in exec and a failure or a step or
+    /// time limit rolls back every change, e.g. eval (if (== (get "a") "on") (set "b" "yes"))
+    fn eval(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let source = request.params.join(" ");
+        let limits = self.script_limits;
+        let outbox_len = self.outbox.len();
+        let mut undo: Vec<Undo> = Vec::new();
+        let mut failed: Option<Response> = None;
+
+        let result = script::run(&source, &limits, |cmd, args| {
+            if !Command::find(cmd).is_some_and(Command::exec_allowed) {
+                failed = Some(Response::create(Status::bad_request(), cmd.to_string()));
+                return Err(ScriptError::Command(cmd.to_string()));
+            }
+
+            let sub =
+                Request::from_parts(cmd, &args).map_err(|e| ScriptError::Syntax(e.to_string()))?;
+            self.save_undo(&sub, &mut undo);
+            let response = self.execute_checked(client, sub);
+            match response.status.code {
+                200..=299 => Ok(Atom::Text(response.body)),
+                404 => Ok(Atom::Nil),
+                _ => {
+                    let msg = response.as_string();
+                    failed = Some(response);
+                    Err(ScriptError::Command(msg))
+                }
+            }
+        });
+
+        match result {
+            Ok(value) => Response::create_ok(value.to_string()),
+            Err(e) => {
+                warn!("eval aborted: {}", e);
+                self.rollback(undo);
+                self.outbox.truncate(outbox_len);
+                match (e, failed) {
+                    (ScriptError::Command(_), Some(response)) => response,
+                    (ScriptError::Limit(msg), _) => Response::create(Status::too_many(), msg),
+                    (e, _) => Response::create(Status::bad_request(), e.to_string()),
+                }
+            }
+        }
+    }
+
+    /// save the current value and metadata of the keys the command may change, once per key
+    fn save_undo(&self, request: &Request, undo: &mut Vec<Undo>) {
+        for key in changed_keys(request) {
+            if !undo.iter().any(|(k, _, _)| k == key) {
+                let value = self.db().get(key);
+                let meta = self.meta.get(&self.current, key).cloned();
+                undo.push((key.to_string(), value, meta));
+            }
+        }
+    }
+
+    /// put back the values and versions saved before an exec or eval, newest first
+    fn rollback(&mut self, undo: Vec<Undo>) {
+        for (key, value, meta) in undo.into_iter().rev() {
+            let len = value.as_ref().map(|value| value.len());
+            match value {
+                Some(value) => self.db().set(&key, value),
+                None => self.db().remove(&key),
+            };
+
+            self.meta.put(&self.current, &key, meta);
+            self.memory.update(&self.current, &key, len);
+        }
+    }
+
+    /// record the key's new version and prior value, then notify the clients watching the key
+    /// in the current database
+    fn key_changed(&mut self, event: &str, key: &str, previous: Option<Vec<u8>>) {
+        let len = self.db().get(key).map(|value| value.len());
+        self.meta
+            .changed(&self.current, key, previous, len.is_none());
+        self.memory.update(&self.current, key, len);
+        self.notify_watchers(event, key);
+    }
+
+    /// notify the watchers of each key that differs between the store before a bulk change, e.g.
+    /// flushdb, loaddb or restore, and the current database
+    fn bulk_changed(&mut self, event: &str, before: &DataStore) {
+        if self.watches.is_empty() {
+            return;
+        }
+
+        let keys: BTreeSet<String> = before.keys().into_iter().chain(self.db().keys()).collect();
+        for key in keys {
+            if before.get(&key) != self.db().get(&key) {
+                self.notify_watchers(event, &key);
+            }
+        }
+    }
+
+    /// queue a notification for each client watching the key in the current database
+    fn notify_watchers(&mut self, event: &str, key: &str) {
+        if self.watches.is_empty() {
+            return;
+        }
+
+        let db = self.current.clone();
+        let watchers = self.watches.subscribers(|topic| {
+            topic.split_once(':').is_some_and(|(name, pattern)| {
+                name == db
+                    && match pattern.strip_suffix('*') {
+                        Some(prefix) => key.starts_with(prefix),
+                        None => key == pattern,
+                    }
+            })
+        });
+
+        for addr in watchers {
+            let message = format!("notify:{}:{}:{}", db, event, key);
+            self.outbox.push((addr, message));
+        }
+    }
+
+    /// subscribe and unsubscribe the client to a channel, or publish a message to the subscribers
+    fn pubsub_request(&mut self, client: Option<SocketAddr>, request: &Request) -> Response {
+        let channel = request.params[0].as_str();
+        if channel.is_empty() {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
+
+        match (request.cmd.as_str(), client) {
+            ("publish", _) => {
+                let message = request.params.get(1).map_or("", |p| p.as_str());
+                let subscribers = self.subscriptions.subscribers(|topic| topic == channel);
+                for addr in subscribers.iter() {
+                    let push = format!("message:{}:{}", channel, message);
+                    self.outbox.push((*addr, push));
+                }
+                Response::create_ok(subscribers.len().to_string())
+            }
+            ("subscribe", Some(addr)) => {
+                // expired subscriptions don't count against the limit
+                self.subscriptions.expire();
+                let renewal = self
+                    .subscriptions
+                    .subscribers(|topic| topic == channel)
+                    .contains(&addr);
+                if !renewal && self.subscriptions.len() >= self.max_subscribers {
+                    warn!("max subscribers reached, refused {} for {}", channel, addr);
+                    return Response::create(Status::too_many(), channel.to_string());
+                }
+
+                let lease = self.subscriptions.subscribe(channel, addr);
+                info!("client {} subscribed to {}", addr, channel);
+                Response::create_ok(lease.to_string())
+            }
+            ("unsubscribe", Some(addr)) => {
+                let removed = self.subscriptions.unsubscribe(channel, addr);
+                Response::create_ok(if removed { "1" } else { "0" }.to_string())
+            }
+            _ => Response::create(
+                Status::bad_request(),
+                "subscribe requires a client".to_string(),
+            ),
+        }
+    }
+
+    /// get the item from key
+    fn get(&self, key: &str) -> Response {
+        match self.db().get(key).map(Value::decode) {
+            Some(Value::Text(body)) => Response::create_ok(body),
+            Some(_) => Response::create(Status::wrong_type(), key.to_string()),
+            _ => Response::create(Status::not_found(), key.to_string()),
+        }
+    }
+
+    /// set the value from key
+    fn set(&mut self, key: &str, value: Vec<u8>) -> Response {
+        if Value::is_typed(&value) {
+            return Response::create(Status::bad_request(), "reserved value prefix".to_string());
+        }
+
+        let is_new = self.max_keys.is_some() && self.db().get(key).is_none();
+        if let Err(response) = self.check_size(key, value.len(), is_new) {
+            return response;
+        }
+
+        let previous = self.db().set(key, value);
+        self.key_changed("set", key, previous.clone());
+        match previous {
+            Some(value) => Response::create_ok(reply_value(value)),
+            None => Response::create_ok("ok".to_string()),
+        }
+    }
+
+    /// check the key and value lengths, and the key count when adding a key, against the limits
+    fn check_size(&self, key: &str, len: usize, is_new: bool) -> Result<(), Response> {
+        if self.max_key_length.is_some_and(|max| key.len() > max) {
+            return Err(Response::create(Status::key_too_long(), key.to_string()));
+        }
+
+        if self.max_value_length.is_some_and(|max| len > max) {
+            return Err(Response::create(Status::value_too_long(), key.to_string()));
+        }
+
+        if is_new && self.max_keys.is_some_and(|max| self.db().dbsize() >= max) {
+            return Err(Response::create(Status::too_many_keys(), key.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// check every element of a loaded store against the limits; the key count includes the
+    /// current keys unless the store replaces them
+    fn check_store(&self, store: &DataStore, replace: bool) -> Result<(), Response> {
+        for key in store.keys() {
+            let len = store.get(&key).map_or(0, |value| value.len());
+            self.check_size(&key, len, false)?;
+        }
+
+        let mut count = store.dbsize();
+        if !replace {
+            count += self
+                .db()
+                .keys()
+                .iter()
+                .filter(|key| store.get(key).is_none())
+                .count();
+        }
+
+        match self.max_keys {
+            Some(max) if count > max => Err(Response::create(
+                Status::too_many_keys(),
+                format!("{} keys", count),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// read the typed value at key; a missing key is None
+    fn read_value(&self, key: &str) -> Option<Value> {
+        self.db().get(key).map(Value::decode)
+    }
+
+    /// store the typed value at key, or remove the key when the value is empty; the encoded
+    /// value is checked against the limits
+    fn write_value(&mut self, key: &str, value: &Value) -> Result<(), Response> {
+        if value.is_empty() {
+            let previous = self.db().remove(key);
+            self.key_changed("del", key, previous);
+        } else {
+            let encoded = value.encode();
+            let is_new = self.db().get(key).is_none();
+            self.check_size(key, encoded.len(), is_new)?;
+            let previous = self.db().set(key, encoded);
+            self.key_changed("set", key, previous);
+        }
+
+        Ok(())
+    }
+
+    /// read the hash at key; a missing key is an empty hash
+    fn read_hash(&self, key: &str) -> Result<BTreeMap<String, String>, Response> {
+        match self.read_value(key) {
+            None => Ok(BTreeMap::new()),
+            Some(Value::Hash(map)) => Ok(map),
+            Some(_) => Err(Response::create(Status::wrong_type(), key.to_string())),
+        }
+    }
+
+    /// the hash commands: hset, hget, hdel, hgetall, hkeys and hlen
+    fn hash_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let key = request.params[0].as_str();
+        let rest = request.params.get(1).map_or("", |p| p.as_str());
+        if key.is_empty() {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
+
+        let mut map = match self.read_hash(key) {
+            Ok(map) => map,
+            Err(response) => return response,
+        };
+
+        match request.cmd.as_str() {
+            "hset" => {
+                let (field, value) = parsers::split2(rest);
+                if field.is_empty() || value.is_empty() {
+                    return Response::create(Status::bad_request(), request.cmd.to_string());
+                }
+                let added = map.insert(field, value).is_none();
+                if let Err(response) = self.write_value(key, &Value::Hash(map)) {
+                    return response;
+                }
+                Response::create_ok(if added { "1" } else { "0" }.to_string())
+            }
+            "hget" => match map.get(rest) {
+                Some(value) => Response::create_ok(value.to_string()),
+                None => Response::create(Status::not_found(), rest.to_string()),
+            },
+            "hdel" => {
+                let count = rest
+                    .split_whitespace()
+                    .filter(|field| map.remove(*field).is_some())
+                    .count();
+                if let Err(response) = self.write_value(key, &Value::Hash(map)) {
+                    return response;
+                }
+                Response::create_ok(count.to_string())
+            }
+            "hgetall" => {
+                let Some(start) = Handler::page_start(rest) else {
+                    return Response::create(Status::bad_request(), request.cmd.to_string());
+                };
+                let entries: Vec<String> = map
+                    .iter()
+                    .map(|(field, value)| {
+                        format!(
+                            "{}:{}",
+                            serde_json::to_string(field).unwrap_or_default(),
+                            serde_json::to_string(value).unwrap_or_default()
+                        )
+                    })
+                    .collect();
+                self.json_page(&entries, start, ('{', '}'))
+            }
+            "hkeys" => {
+                let fields: Vec<&String> = map.keys().collect();
+                Response::create_ok(format!("{:?}", fields))
+            }
+            _ => Response::create_ok(map.len().to_string()),
+        }
+    }
+
+    /// read the list at key; a missing key is an empty list
+    fn read_list(&self, key: &str) -> Result<VecDeque<String>, Response> {
+        match self.read_value(key) {
+            None => Ok(VecDeque::new()),
+            Some(Value::List(list)) => Ok(list),
+            Some(_) => Err(Response::create(Status::wrong_type(), key.to_string())),
+        }
+    }
+
+    /// the list commands: lpush, rpush, lpop, rpop, lrange, llen and ltrim
+    fn list_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let key = request.params[0].as_str();
+        let rest = request.params.get(1).map_or("", |p| p.as_str());
+        if key.is_empty() {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
+
+        let mut list = match self.read_list(key) {
+            Ok(list) => list,
+            Err(response) => return response,
+        };
+
+        match request.cmd.as_str() {
+            "lpush" | "rpush" => {
+                let values: Vec<&str> = rest.split_whitespace().collect();
+                if values.is_empty() {
+                    return Response::create(Status::bad_request(), request.cmd.to_string());
+                }
+                for value in values {
+                    if request.cmd == "lpush" {
+                        list.push_front(value.to_string());
+                    } else {
+                        list.push_back(value.to_string());
+                    }
+                }
+                let len = list.len();
+                if let Err(response) = self.write_value(key, &Value::List(list)) {
+                    return response;
+                }
+                Response::create_ok(len.to_string())
+            }
+            "lpop" | "rpop" => {
+                let value = if request.cmd == "lpop" {
+                    list.pop_front()
+                } else {
+                    list.pop_back()
+                };
+                match value {
+                    Some(value) => {
+                        if let Err(response) = self.write_value(key, &Value::List(list)) {
+                            return response;
+                        }
+                        Response::create_ok(value)
+                    }
+                    None => Response::create(Status::not_found(), key.to_string()),
+                }
+            }
+            "lrange" => match parse_range(rest) {
+                Some((start, stop)) => {
+                    let items: Vec<String> = list.into_iter().collect();
+                    self.range_page(&items, start, stop)
+                }
+                None => Response::create(Status::bad_request(), request.cmd.to_string()),
+            },
+            "ltrim" => match parse_range(rest) {
+                Some((start, stop)) => {
+                    let list = match index_range(start, stop, list.len()) {
+                        Some((start, stop)) => list.drain(start..=stop).collect(),
+                        None => VecDeque::new(),
+                    };
+                    if let Err(response) = self.write_value(key, &Value::List(list)) {
+                        return response;
+                    }
+                    Response::create_ok("ok".to_string())
+                }
+                None => Response::create(Status::bad_request(), request.cmd.to_string()),
+            },
+            _ => Response::create_ok(list.len().to_string()),
+        }
+    }
+
+    /// return the range as a json array that fits in one datagram; a partial status means
+    /// there are more elements, so request again starting after the last one returned
+    fn range_page(&self, list: &[String], start: i64, stop: i64) -> Response {
+        let Some((start, stop)) = index_range(start, stop, list.len()) else {
+            return Response::create_ok("[]".to_string());
+        };
+
+        let entries: Vec<String> = list[..=stop]
+            .iter()
+            .map(|item| serde_json::to_string(item).unwrap_or_default())
+            .collect();
+        self.json_page(&entries, start, ('[', ']'))
+    }
+
+    /// join the json entries from start that fit in one datagram inside the brackets, e.g.
+    /// [ and ] or { and }; a partial status means there are more entries, so request again
+    /// starting after the last one returned
+    fn json_page(&self, entries: &[String], start: usize, brackets: (char, char)) -> Response {
+        let (open, close) = brackets;
+        if start >= entries.len() {
+            return Response::create_ok(format!("{}{}", open, close));
+        }
+
+        // the status header plus the brackets
+        let mut size = Response::create(Status::partial(), format!("{}{}", open, close))
+            .as_string()
+            .len();
+        let mut count = 0;
+        for entry in &entries[start..] {
+            let sz = entry.len() + 1;
+            if size + sz > self.max_payload {
+                break;
+            }
+            size += sz;
+            count += 1;
+        }
+
+        let body = format!(
+            "{}{}{}",
+            open,
+            entries[start..start + count].join(","),
+            close
+        );
+        if count == 0 {
+            Response::create(Status::too_large(), start.to_string())
+        } else if start + count < entries.len() {
+            Response::create(Status::partial(), body)
+        } else {
+            Response::create_ok(body)
+        }
+    }
+
+    /// the start of a page from the optional param; zero when it is empty
+    fn page_start(param: &str) -> Option<usize> {
+        if param.is_empty() {
+            return Some(0);
+        }
+
+        parsers::as_number::<usize>(param).ok()
+    }
+
+    /// read the set at key; a missing key is an empty set
+    fn read_set(&self, key: &str) -> Result<BTreeSet<String>, Response> {
+        match self.read_value(key) {
+            None => Ok(BTreeSet::new()),
+            Some(Value::Set(set)) => Ok(set),
+            Some(_) => Err(Response::create(Status::wrong_type(), key.to_string())),
+        }
+    }
+
+    /// the set commands: sadd, srem, smembers, sismember and scard
+    fn set_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let key = request.params[0].as_str();
+        let rest = request.params.get(1).map_or("", |p| p.as_str());
+        if key.is_empty() {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
+
+        let mut set = match self.read_set(key) {
+            Ok(set) => set,
+            Err(response) => return response,
+        };
+
+        match request.cmd.as_str() {
+            "sadd" | "srem" => {
+                let members: Vec<&str> = rest.split_whitespace().collect();
+                if members.is_empty() {
+                    return Response::create(Status::bad_request(), request.cmd.to_string());
+                }
+                let count = members
+                    .into_iter()
+                    .filter(|m| {
+                        if request.cmd == "sadd" {
+                            set.insert(m.to_string())
+                        } else {
+                            set.remove(*m)
+                        }
+                    })
+                    .count();
+                if let Err(response) = self.write_value(key, &Value::Set(set)) {
+                    return response;
+                }
+                Response::create_ok(count.to_string())
+            }
+            "smembers" => {
+                let Some(start) = Handler::page_start(rest) else {
+                    return Response::create(Status::bad_request(), request.cmd.to_string());
+                };
+                let entries: Vec<String> = set
+                    .iter()
+                    .map(|member| serde_json::to_string(member).unwrap_or_default())
+                    .collect();
+                self.json_page(&entries, start, ('[', ']'))
+            }
+            "sismember" => {
+                let found = set.contains(rest);
+                Response::create_ok(if found { "1" } else { "0" }.to_string())
+            }
+            _ => Response::create_ok(set.len().to_string()),
+        }
+    }
+
+    /// read the sorted set at key; a missing key is an empty sorted set
+    fn read_zset(&self, key: &str) -> Result<BTreeMap<String, f64>, Response> {
+        match self.read_value(key) {
+            None => Ok(BTreeMap::new()),
+            Some(Value::ZSet(zset)) => Ok(zset),
+            Some(_) => Err(Response::create(Status::wrong_type(), key.to_string())),
+        }
+    }
+
+    /// the sorted set commands: zadd, zrange, zrank, zscore and zincrby
+    fn zset_request(&mut self, _client: Option<SocketAddr>, request: &Request) -> Response {
+        let key = request.params[0].as_str();
+        let rest = request.params.get(1).map_or("", |p| p.as_str());
+        if key.is_empty() {
+            return Response::create(Status::bad_request(), request.cmd.to_string());
+        }
+
+        let mut zset = match self.read_zset(key) {
+            Ok(zset) => zset,
+            Err(response) => return response,
+        };
+
+        let bad_request = Response::create(Status::bad_request(), request.cmd.to_string());
+        match request.cmd.as_str() {
+            "zadd" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.is_empty() || args.len() % 2 != 0 {
+                    return bad_request;
+                }
+                let mut added = 0;
+                for pair in args.chunks(2) {
+                    let Some(score) = parse_score(pair[0]) else {
+                        return bad_request;
+                    };
+                    if zset.insert(pair[1].to_string(), score).is_none() {
+                        added += 1;
+                    }
+                }
+                if let Err(response) = self.write_value(key, &Value::ZSet(zset)) {
+                    return response;
+                }
+                Response::create_ok(added.to_string())
+            }
+            "zincrby" => {
+                let (incr, member) = parsers::split2(rest);
+                match parse_score(&incr) {
+                    Some(incr) if !member.is_empty() => {
+                        let score = zset.get(&member).copied().unwrap_or(0.0) + incr;
+                        if !score.is_finite() {
+                            return bad_request;
+                        }
+                        zset.insert(member, score);
+                        if let Err(response) = self.write_value(key, &Value::ZSet(zset)) {
+                            return response;
+                        }
+                        Response::create_ok(score.to_string())
+                    }
+                    _ => bad_request,
+                }
+            }
+            "zrange" => match parse_range(rest) {
+                Some((start, stop)) => self.range_page(&by_score(&zset), start, stop),
+                None => bad_request,
+            },
+            "zrank" => match by_score(&zset).iter().position(|m| m == rest) {
+                Some(rank) => Response::create_ok(rank.to_string()),
+                None => Response::create(Status::not_found(), rest.to_string()),
+            },
+            _ => match zset.get(rest) {
+                Some(score) => Response::create_ok(score.to_string()),
+                None => Response::create(Status::not_found(), rest.to_string()),
+            },
+        }
+    }
+
+    fn del(&mut self, key: &str) -> Response {
+        if let Some(value) = self.db().remove(key) {
+            self.key_changed("del", key, Some(value.clone()));
+            Response::create_ok(reply_value(value))
+        } else {
+            Response::create_ok("ok".to_string())
+        }
+    }
+
+    /// the filename from the request or the data file configured for the current database
+    fn data_filename(&self, filename: &str) -> String {
+        if filename.is_empty() {
+            self.database().data_file.clone().unwrap_or_default()
+        } else {
+            filename.to_string()
+        }
+    }
+
+    /// the snapshots for the current database
+    fn current_snapshots(&self) -> Option<Snapshots> {
+        self.snapshots
+            .as_ref()
+            .map(|s| s.for_database(&self.current, DEFAULT_DB))
+    }
+
+    /// load the file into the current database
+    fn loaddb(&mut self, filename: &str) -> Response {
+        let filename = self.data_filename(filename);
+        let store = DataStore::create();
+        let Ok(sz) = store.loaddb(&filename) else {
+            return Response::create(Status::bad_request(), filename);
+        };
+
+        // load into a new store first so a file over the limits adds nothing
+        if let Err(response) = self.check_store(&store, false) {
+            warn!("refused {}: {}", filename, response.as_string());
+            return response;
+        }
+
+        // only the keys the file inserts or changes get a new version and history
+        for key in store.keys() {
+            if let Some(value) = store.get(&key) {
+                let previous = self.db().set(&key, value.clone());
+                if previous.as_ref() != Some(&value) {
+                    self.key_changed("loaddb", &key, previous);
+                }
+            }
+        }
+        Response::create_ok(sz.to_string())
+    }
+
+    /// load the file into a new store then swap it in so a bad file leaves the current data alone
+    fn loaddb_replace(&mut self, filename: &str) -> Response {
+        let filename = self.data_filename(filename);
+        let store = DataStore::create();
+        match store.loaddb(&filename) {
+            Ok(sz) => {
+                if let Err(response) = self.check_store(&store, true) {
+                    warn!("refused {}: {}", filename, response.as_string());
+                    return response;
+                }
+
+                info!(
+                    "replaced {} with {} elements from {}",
+                    self.current, sz, filename
+                );
+                let before = self.replace_store(store);
+                self.reset_versions();
+                self.bulk_changed("loaddb", &before);
+                Response::create_ok(sz.to_string())
+            }
+            Err(_) => Response::create(Status::bad_request(), filename),
+        }
+    }
+
+    /// save to the file and/or the next snapshot version when snapshots are configured
+    fn savedb(&mut self, filename: &str) -> Response {
+        let filename = self.data_filename(filename);
+        let snapshots = self.current_snapshots();
+        if filename.is_empty() && snapshots.is_none() {
+            return Response::create(Status::bad_request(), filename);
+        }
+
+        let mut size = self.db().dbsize();
+        if !filename.is_empty() {
+            match self.db().savedb(&filename) {
+                Ok(sz) => size = sz,
+                Err(_) => return Response::create(Status::bad_request(), filename),
+            }
+        }
+
+        if let Some(snapshots) = snapshots {
+            if let Err(e) = snapshots.save(self.db()) {
+                error!("snapshot error: {}", e);
+                return Response::create(Status::bad_request(), e.to_string());
+            }
+        }
+
+        self.last_save = get_ts();
+        Response::create_ok(size.to_string())
+    }
+
+    /// the time between the snapshots of every database; none without snapshots or an interval
+    pub fn snapshot_interval(&self) -> Option<Duration> {
+        self.config
+            .snapshots
+            .as_ref()?
+            .interval
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// save the next snapshot version of every database, e.g. on the snapshot interval
+    pub fn save_snapshots(&mut self) {
+        let Some(snapshots) = &self.snapshots else {
+            return;
+        };
+
+        for (name, db) in &self.databases {
+            if let Err(e) = snapshots.for_database(name, DEFAULT_DB).save(&db.store) {
+                error!("snapshot error for {}: {}", name, e);
+            }
+        }
+        self.last_save = get_ts();
+    }
+
+    /// list the snapshot versions as version:timestamp
+    fn list_snapshots(&self) -> Response {
+        let Some(snapshots) = self.current_snapshots() else {
+            return Response::create(
+                Status::bad_request(),
+                "snapshots not configured".to_string(),
+            );
+        };
+
+        match snapshots.list() {
+            Ok(list) => {
+                let versions: Vec<String> = list.iter().map(|s| s.as_string()).collect();
+                Response::create_ok(format!("{:?}", versions))
+            }
+            Err(e) => Response::create(Status::bad_request(), e.to_string()),
+        }
+    }
+
+    /// replace the current data with the snapshot version
+    fn restore(&mut self, version: &str) -> Response {
+        let Some(snapshots) = self.current_snapshots() else {
+            return Response::create(
+                Status::bad_request(),
+                "snapshots not configured".to_string(),
+            );
+        };
+
+        let version = match parsers::as_number::<u64>(version) {
+            Ok(v) => v,
+            Err(_) => return Response::create(Status::bad_request(), version.to_string()),
+        };
+
+        match snapshots.restore(version) {
+            Ok(db) => {
+                if let Err(response) = self.check_store(&db, true) {
+                    return response;
+                }
+                info!("restored {} snapshot version: {}", self.current, version);
+                let size = db.dbsize();
+                let before = self.replace_store(db);
+                self.reset_versions();
+                self.bulk_changed("restore", &before);
+                Response::create_ok(size.to_string())
+            }
+            Err(e) => Response::create(Status::not_found(), e.to_string()),
+        }
+    }
+
+    /// return the number of elements in the default database's k/v map
+    pub fn dbsize(&self) -> usize {
+        self.databases
+            .get(DEFAULT_DB)
+            .map_or(0, |db| db.store.dbsize())
+    }
+}
+
+/// the previous value to reply with; a typed value is shown as its type, e.g. (hash)
+fn reply_value(value: Vec<u8>) -> String {
+    match Value::decode(value) {
+        Value::Text(text) => text,
+        value => format!("({})", value.type_name()),
+    }
+}
+
+/// the keys a sub-command of exec may change
+fn changed_keys(request: &Request) -> Vec<&str> {
+    let args = request.args();
+    match request.cmd.as_str() {
+        "rename" | "renamenx" | "copy" => args.into_iter().take(2).collect(),
+        _ => request
+            .params
+            .first()
+            .map(|key| key.as_str())
+            .filter(|key| !key.is_empty())
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// the approximate bytes a write adds: its key and value plus the per-key overhead
+fn write_size(request: &Request) -> usize {
+    request.params.iter().map(|p| p.len()).sum::<usize>() + KEY_OVERHEAD
+}
+
+/// parse the start and stop indexes for the range commands
+fn parse_range(params: &str) -> Option<(i64, i64)> {
+    let args: Vec<&str> = params.split_whitespace().collect();
+    if args.len() != 2 {
+        return None;
+    }
+
+    let start = parsers::as_number::<i64>(args[0]).ok()?;
+    let stop = parsers::as_number::<i64>(args[1]).ok()?;
+
+    Some((start, stop))
+}
+
+/// parse a sorted set score; nan and infinity can't be stored as json so are not valid
+fn parse_score(value: &str) -> Option<f64> {
+    parsers::as_number::<f64>(value)
+        .ok()
+        .filter(|score| score.is_finite())
+}
+
+/// return the unix timestamp
+fn get_ts() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("seconds")
+        .as_secs()
+}
+
+/// return the current time in nanoseconds
+fn get_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("nanos")
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_handler() -> Handler {
+        let db = DataStore::create();
+        Handler::new(db)
+    }
+
+    /// the test server config, changed by configure
+    fn test_config(configure: impl FnOnce(&mut Config)) -> Config {
+        let mut config = Config::read_config("tests/server-config.toml").unwrap();
+        configure(&mut config);
+        config
+    }
+
+    /// a handler with an empty store and the test server config, changed by configure
+    fn create_handler_with(configure: impl FnOnce(&mut Config)) -> Handler {
+        Handler::create(DataStore::create(), &test_config(configure))
+    }
+
+    fn send(handler: &mut Handler, msg: &str) -> Response {
+        let request = Request::from_message(msg).unwrap();
+        handler.handle_request(request)
+    }
+
+    #[test]
+    fn get_set_del_dbsize() {
+        let mut handler = create_handler();
+        assert_eq!(handler.db().dbsize(), 0);
+
+        let key = "1234.MyKey";
+        let value = "This is a test value";
+        let msg = format!("set {} {}", key, value);
+        let request = Request::from_message(msg.as_str()).unwrap();
+        let response = handler.handle_request(request.clone());
+        info!("{:?}", response);
+        assert_eq!(handler.db().dbsize(), 1);
+
+        let response = handler.handle_request(request.clone());
+        info!("{:?}", response);
+        assert_eq!(handler.db().dbsize(), 1);
 
         let msg = format!("get {}", key);
         let request = Request::from_message(msg.as_str()).unwrap();
@@ -266,106 +2418,1445 @@ mod tests {
         let request = Request::from_message("dbsize").unwrap();
         let response = handler.handle_request(request);
         info!("dbsize {:?}", response);
-        assert_eq!(handler.db.dbsize(), 1);
+        assert_eq!(handler.db().dbsize(), 1);
+        assert_eq!(response.as_usize().unwrap(), 1);
+    }
+
+    #[test]
+    fn bad_set() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "set".to_string(),
+            params: vec!["mykey".to_string()],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn bad_get() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "get".to_string(),
+            params: vec!["my-bad-key".to_string()],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 404);
+    }
+
+    #[test]
+    fn del() {
+        let mut handler = create_handler();
+        let rq = Request {
+            cmd: "set".to_string(),
+            params: vec!["mykey".to_string(), "my value".to_string()],
+        };
+        let _ = handler.handle_request(rq);
+
+        assert_eq!(handler.db().dbsize(), 1);
+
+        let request = Request {
+            cmd: "del".to_string(),
+            params: vec!["mykey".to_string()],
+        };
+        let response = handler.handle_request(request.clone());
+        assert_eq!(response.status.code, 200);
+        let response = handler.handle_request(request.clone());
+        assert_eq!(response.status.code, 200);
+    }
+
+    #[test]
+    fn set_del_typed_reply() {
+        let mut handler = create_handler();
+        send(&mut handler, "hset u100 name john");
+        let response = send(&mut handler, "set u100 plain");
+        assert_eq!(response.body, "(hash)");
+
+        send(&mut handler, "rpush q1 a");
+        let response = send(&mut handler, "del q1");
+        assert_eq!(response.body, "(list)");
+
+        send(&mut handler, "set k1 one");
+        let response = send(&mut handler, "del k1");
+        assert_eq!(response.body, "one");
+
+        // a value that would read back as a typed value is refused
+        let response = send(&mut handler, r#"set k2 @hash:{"a":"b"}"#);
+        assert_eq!(response.status.code, 400);
+        let response = send(&mut handler, "set k2 @hash:not-json");
+        assert_eq!(response.status.code, 200);
+    }
+
+    #[test]
+    fn keys() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "keys".to_string(),
+            params: vec![],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 200);
+    }
+
+    #[test]
+    fn loaddb() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "loaddb".to_string(),
+            params: vec!["tests/users-ref.kv".to_string()],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 200);
+    }
+
+    #[test]
+    fn loaddb_bad() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "loaddb".to_string(),
+            params: vec!["badfil/users-ref.kv".to_string()],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn savedb() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "savedb".to_string(),
+            params: vec!["tests/test-out.kv".to_string()],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 200);
+    }
+
+    #[test]
+    fn savedb_bad() {
+        let mut handler = create_handler();
+        let request = Request {
+            cmd: "savedb".to_string(),
+            params: vec!["not-a-good-file/test-out.kv".to_string()],
+        };
+        let response = handler.handle_request(request);
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn snapshot_save_restore() {
+        let folder = "tests/handler-snapshots-out";
+        let _ = std::fs::remove_dir_all(folder);
+        let mut handler = create_handler_with(|config| {
+            config.snapshots = Some(crate::config::SnapshotConfig {
+                folder: folder.to_string(),
+                keep: Some(3),
+                ..Default::default()
+            })
+        });
+
+        send(&mut handler, "set k1 good value");
+        let response = send(&mut handler, "savedb");
+        assert_eq!(response.status.code, 200);
+
+        send(&mut handler, "set k1 bad value");
+        send(&mut handler, "set k2 another bad value");
+        assert_eq!(handler.dbsize(), 2);
+
+        let response = send(&mut handler, "listsnapshots");
+        assert_eq!(response.status.code, 200);
+        assert!(response.body.contains("1:"));
+
+        let client = SocketAddr::from(([127, 0, 0, 1], 9204));
+        let request = Request::from_message("restore 1").unwrap();
+        let response = handler.handle_client_request(client, request);
+        assert_eq!(response.status.code, 403);
+        assert_eq!(handler.dbsize(), 2);
+
+        let response = send(&mut handler, "restore 1");
+        assert_eq!(response.status.code, 200);
+        assert_eq!(handler.dbsize(), 1);
+        let response = send(&mut handler, "get k1");
+        assert_eq!(response.body, "good value");
+
+        let response = send(&mut handler, "restore 42");
+        assert_eq!(response.status.code, 404);
+        let _ = std::fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn interval_snapshots() {
+        let folder = "tests/handler-interval-snapshots-out";
+        let _ = std::fs::remove_dir_all(folder);
+        let mut handler = create_handler_with(|config| {
+            config.snapshots = Some(crate::config::SnapshotConfig {
+                folder: folder.to_string(),
+                keep: Some(3),
+                ..Default::default()
+            })
+        });
+        assert_eq!(handler.snapshot_interval(), None);
+
+        send(&mut handler, "config set snapshots.interval 60");
+        assert_eq!(handler.snapshot_interval(), Some(Duration::from_secs(60)));
+        send(&mut handler, "set k1 one");
+        handler.save_snapshots();
+        handler.save_snapshots();
+        let response = send(&mut handler, "listsnapshots");
+        assert!(response.body.contains("2:"));
+
+        send(&mut handler, "config set snapshots.interval 0");
+        assert_eq!(handler.snapshot_interval(), None);
+        let _ = std::fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn bulk_change_notifications() {
+        let folder = "tests/handler-bulk-notify-out";
+        let _ = std::fs::remove_dir_all(folder);
+        let mut handler = create_handler_with(|config| {
+            config.snapshots = Some(crate::config::SnapshotConfig {
+                folder: folder.to_string(),
+                keep: Some(3),
+                ..Default::default()
+            })
+        });
+        let watcher: SocketAddr = "127.0.0.1:9023".parse().unwrap();
+        let request = Request::from_message("watch k*").unwrap();
+        handler.handle_client_request(watcher, request);
+        let request = Request::from_message("watch u100").unwrap();
+        handler.handle_client_request(watcher, request);
+        let notified = |handler: &mut Handler| -> Vec<String> {
+            handler.drain_messages().into_iter().map(|m| m.1).collect()
+        };
+
+        send(&mut handler, "set k1 good value");
+        send(&mut handler, "savedb");
+        send(&mut handler, "set k1 bad value");
+        send(&mut handler, "set k2 another bad value");
+        notified(&mut handler);
+
+        send(&mut handler, "restore 1");
+        assert_eq!(
+            notified(&mut handler),
+            vec!["notify:default:restore:k1", "notify:default:restore:k2"]
+        );
+
+        send(&mut handler, "flushdb");
+        assert_eq!(notified(&mut handler), vec!["notify:default:flushdb:k1"]);
+
+        send(&mut handler, "loaddb tests/users-ref.kv");
+        assert_eq!(notified(&mut handler), vec!["notify:default:loaddb:u100"]);
+        send(&mut handler, "loaddb tests/users-ref.kv");
+        assert!(notified(&mut handler).is_empty());
+
+        send(&mut handler, "set k1 value");
+        notified(&mut handler);
+        send(&mut handler, "loaddb --replace tests/users-ref.kv");
+        assert_eq!(notified(&mut handler), vec!["notify:default:loaddb:k1"]);
+        let _ = std::fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn snapshots_not_configured() {
+        let mut handler = create_handler();
+        let response = send(&mut handler, "listsnapshots");
+        assert_eq!(response.status.code, 400);
+        let response = send(&mut handler, "restore 1");
+        assert_eq!(response.status.code, 400);
+        let response = send(&mut handler, "savedb");
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn select_database() {
+        let mut handler = create_handler_with(|config| {
+            config.databases = Some(BTreeMap::from([
+                ("users".to_string(), "tests/users-ref.kv".to_string()),
+                (
+                    "config".to_string(),
+                    "tests/select-config-out.kv".to_string(),
+                ),
+            ]))
+        });
+        let addr: SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:9002".parse().unwrap();
+
+        let request = Request::from_message("select users").unwrap();
+        let response = handler.handle_client_request(addr, request);
+        assert_eq!(response.status.code, 200);
+
+        let request = Request::from_message("get u100").unwrap();
+        let response = handler.handle_client_request(addr, request.clone());
+        assert_eq!(response.status.code, 200);
+        let response = handler.handle_client_request(other, request);
+        assert_eq!(response.status.code, 404);
+
+        let request = Request::from_message("select flarb").unwrap();
+        let response = handler.handle_client_request(addr, request);
+        assert_eq!(response.status.code, 404);
+
+        let response = send(&mut handler, "select users");
+        assert_eq!(response.status.code, 400);
+
+        // an idle selection expires and is dropped by the next select
+        let request = Request::from_message("select users").unwrap();
+        handler.handle_client_request(other, request);
+        handler.selected.get_mut(&other).unwrap().1 = 0;
+        let request = Request::from_message("get u100").unwrap();
+        let response = handler.handle_client_request(other, request);
+        assert_eq!(response.status.code, 404);
+
+        let request = Request::from_message("select default").unwrap();
+        handler.handle_client_request(addr, request);
+        assert!(handler.selected.is_empty());
+    }
+
+    #[test]
+    fn database_prefix() {
+        let mut handler = create_handler_with(|config| {
+            config.databases = Some(BTreeMap::from([
+                ("users".to_string(), "tests/users-ref.kv".to_string()),
+                (
+                    "config".to_string(),
+                    "tests/prefix-config-out.kv".to_string(),
+                ),
+            ]))
+        });
+        send(&mut handler, "config:set k1 config value");
+        assert_eq!(handler.dbsize(), 0);
+
+        let response = send(&mut handler, "config:get k1");
+        assert_eq!(response.body, "config value");
+        let response = send(&mut handler, "config:dbsize");
         assert_eq!(response.as_usize().unwrap(), 1);
+        let response = send(&mut handler, "users:dbsize");
+        assert!(response.as_usize().unwrap() >= 10);
+
+        let response = send(&mut handler, "flarb:dbsize");
+        assert_eq!(response.status.code, 404);
+
+        let response = send(&mut handler, "databases");
+        assert!(response.body.contains("config:1"));
+    }
+
+    #[test]
+    fn database_savedb_data_file() {
+        let mut handler = create_handler_with(|config| {
+            config.databases = Some(BTreeMap::from([
+                ("users".to_string(), "tests/users-ref.kv".to_string()),
+                (
+                    "config".to_string(),
+                    "tests/savedb-config-out.kv".to_string(),
+                ),
+            ]))
+        });
+        send(&mut handler, "config:set k1 config value");
+        let response = send(&mut handler, "config:savedb");
+        assert_eq!(response.status.code, 200);
+
+        send(&mut handler, "config:del k1");
+        let response = send(&mut handler, "config:loaddb");
+        assert_eq!(response.status.code, 200);
+        let response = send(&mut handler, "config:get k1");
+        assert_eq!(response.body, "config value");
+        let _ = std::fs::remove_file("tests/savedb-config-out.kv");
+    }
+
+    #[test]
+    fn flushdb_admin() {
+        let mut handler = create_handler();
+        handler.admin_keys = vec!["secret".to_string()];
+        let addr: SocketAddr = "127.0.0.1:9003".parse().unwrap();
+        send(&mut handler, "set k1 one");
+
+        let request = Request::from_message("flushdb").unwrap();
+        let response = handler.handle_client_request(addr, request.clone());
+        assert_eq!(response.status.code, 403);
+        assert_eq!(handler.dbsize(), 1);
+
+        let auth = Request::from_message("auth wrong").unwrap();
+        let response = handler.handle_client_request(addr, auth);
+        assert_eq!(response.status.code, 403);
+
+        let auth = Request::from_message("auth secret").unwrap();
+        let response = handler.handle_client_request(addr, auth);
+        assert_eq!(response.status.code, 200);
+
+        let response = handler.handle_client_request(addr, request);
+        assert_eq!(response.status.code, 200);
+        assert_eq!(handler.dbsize(), 0);
+    }
+
+    #[test]
+    fn rename_copy_exists() {
+        let mut handler = create_handler();
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "set k2 two");
+
+        let response = send(&mut handler, "rename k1 k3");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "exists k1 k2 k3");
+        assert_eq!(response.body, "2");
+
+        let response = send(&mut handler, "renamenx k3 k2");
+        assert_eq!(response.body, "0");
+        let response = send(&mut handler, "get k3");
+        assert_eq!(response.body, "one");
+
+        let response = send(&mut handler, "copy k3 k4");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "copy k3 k2");
+        assert_eq!(response.body, "0");
+        let response = send(&mut handler, "get k4");
+        assert_eq!(response.body, "one");
+
+        let response = send(&mut handler, "rename nokey k5");
+        assert_eq!(response.status.code, 404);
+        let response = send(&mut handler, "rename k1");
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn loaddb_replace() {
+        let mut handler = create_handler();
+        send(&mut handler, "set extra-key value");
+
+        let response = send(&mut handler, "loaddb --replace badfile/users.kv");
+        assert_eq!(response.status.code, 400);
+        assert_eq!(handler.dbsize(), 1);
+
+        let client = SocketAddr::from(([127, 0, 0, 1], 9203));
+        let request = Request::from_message("loaddb --replace tests/users-ref.kv").unwrap();
+        let response = handler.handle_client_request(client, request);
+        assert_eq!(response.status.code, 403);
+        assert_eq!(handler.dbsize(), 1);
+
+        let response = send(&mut handler, "loaddb --replace tests/users-ref.kv");
+        assert_eq!(response.status.code, 200);
+        let response = send(&mut handler, "get extra-key");
+        assert_eq!(response.status.code, 404);
+    }
+
+    #[test]
+    fn hash_commands() {
+        let mut handler = create_handler();
+        let response = send(&mut handler, "hset u100 first_name john");
+        assert_eq!(response.body, "1");
+        send(&mut handler, "hset u100 last_name smith");
+        let response = send(&mut handler, "hset u100 first_name jon");
+        assert_eq!(response.body, "0");
+
+        let response = send(&mut handler, "hget u100 first_name");
+        assert_eq!(response.body, "jon");
+        let response = send(&mut handler, "hget u100 email");
+        assert_eq!(response.status.code, 404);
+        let response = send(&mut handler, "hlen u100");
+        assert_eq!(response.body, "2");
+        let response = send(&mut handler, "hkeys u100");
+        assert_eq!(response.body, r#"["first_name", "last_name"]"#);
+        let response = send(&mut handler, "hgetall u100");
+        assert_eq!(response.body, r#"{"first_name":"jon","last_name":"smith"}"#);
+        let response = send(&mut handler, "type u100");
+        assert_eq!(response.body, "hash");
+
+        let response = send(&mut handler, "hdel u100 first_name last_name nope");
+        assert_eq!(response.body, "2");
+        let response = send(&mut handler, "type u100");
+        assert_eq!(response.body, "none");
+    }
+
+    #[test]
+    fn hash_wrong_type() {
+        let mut handler = create_handler();
+        send(&mut handler, "set k1 plain value");
+        let response = send(&mut handler, "hset k1 field value");
+        assert_eq!(response.status.code, 409);
+        let response = send(&mut handler, "hget k1 field");
+        assert_eq!(response.status.code, 409);
+
+        send(&mut handler, "hset h1 field value");
+        let response = send(&mut handler, "get h1");
+        assert_eq!(response.status.code, 409);
+        let response = send(&mut handler, "set k2 @hash:{}");
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn hash_savedb_loaddb() {
+        let filename = "tests/hash-out.kv";
+        let mut handler = create_handler();
+        send(&mut handler, "hset u100 name john smith");
+        send(&mut handler, "hset u100 email john@example.com");
+        let response = send(&mut handler, &format!("savedb {}", filename));
+        assert_eq!(response.status.code, 200);
+
+        let mut handler = create_handler();
+        let response = send(&mut handler, &format!("loaddb {}", filename));
+        assert_eq!(response.status.code, 200);
+        let response = send(&mut handler, "hget u100 name");
+        assert_eq!(response.body, "john smith");
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn list_commands() {
+        let mut handler = create_handler();
+        let response = send(&mut handler, "rpush jobs j1 j2 j3");
+        assert_eq!(response.body, "3");
+        let response = send(&mut handler, "lpush jobs j0");
+        assert_eq!(response.body, "4");
+        let response = send(&mut handler, "lrange jobs 0 -1");
+        assert_eq!(response.body, r#"["j0","j1","j2","j3"]"#);
+        let response = send(&mut handler, "lrange jobs 1 2");
+        assert_eq!(response.body, r#"["j1","j2"]"#);
+
+        let response = send(&mut handler, "lpop jobs");
+        assert_eq!(response.body, "j0");
+        let response = send(&mut handler, "rpop jobs");
+        assert_eq!(response.body, "j3");
+        let response = send(&mut handler, "llen jobs");
+        assert_eq!(response.body, "2");
+
+        let response = send(&mut handler, "ltrim jobs 1 -1");
+        assert_eq!(response.status.code, 200);
+        let response = send(&mut handler, "lrange jobs 0 -1");
+        assert_eq!(response.body, r#"["j2"]"#);
+
+        send(&mut handler, "rpop jobs");
+        let response = send(&mut handler, "rpop jobs");
+        assert_eq!(response.status.code, 404);
+        let response = send(&mut handler, "type jobs");
+        assert_eq!(response.body, "none");
+
+        let response = send(&mut handler, "lrange jobs 0");
+        assert_eq!(response.status.code, 400);
+        send(&mut handler, "set plain value");
+        let response = send(&mut handler, "lpush plain j1");
+        assert_eq!(response.status.code, 409);
+    }
+
+    #[test]
+    fn hgetall_paging() {
+        let mut handler = create_handler();
+        handler.max_payload = 64;
+        for n in 0..20 {
+            send(
+                &mut handler,
+                &format!("hset u100 field-{:02} value-{:02}", n, n),
+            );
+        }
+
+        let mut fields: BTreeMap<String, String> = BTreeMap::new();
+        loop {
+            let response = send(&mut handler, &format!("hgetall u100 {}", fields.len()));
+            assert!(response.as_string().len() <= 64);
+            let page: BTreeMap<String, String> = serde_json::from_str(&response.body).unwrap();
+            fields.extend(page);
+            if response.status.code == 200 {
+                break;
+            }
+            assert_eq!(response.status.code, 206);
+        }
+
+        assert_eq!(fields.len(), 20);
+        assert_eq!(fields["field-19"], "value-19");
+        assert_eq!(send(&mut handler, "hgetall u100 x").status.code, 400);
+    }
+
+    #[test]
+    fn lrange_paging() {
+        let mut handler = create_handler();
+        handler.max_payload = 64;
+        for n in 0..20 {
+            send(&mut handler, &format!("rpush jobs job-{:04}", n));
+        }
+
+        let mut start = 0;
+        let mut items: Vec<String> = Vec::new();
+        loop {
+            let response = send(&mut handler, &format!("lrange jobs {} -1", start));
+            assert!(response.as_string().len() <= 64);
+            let page: Vec<String> = serde_json::from_str(&response.body).unwrap();
+            start += page.len();
+            items.extend(page);
+            if response.status.code == 200 {
+                break;
+            }
+            assert_eq!(response.status.code, 206);
+        }
+
+        assert_eq!(items.len(), 20);
+        assert_eq!(items[19], "job-0019");
     }
 
     #[test]
-    fn bad_set() {
+    fn smembers_paging() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "set".to_string(),
-            params: vec!["mykey".to_string()],
-        };
-        let response = handler.handle_request(request);
-        assert_eq!(response.status.code, 400);
+        handler.max_payload = 64;
+        for n in 0..20 {
+            send(&mut handler, &format!("sadd tags tag-{:02}", n));
+        }
+
+        let mut members: Vec<String> = Vec::new();
+        loop {
+            let response = send(&mut handler, &format!("smembers tags {}", members.len()));
+            assert!(response.as_string().len() <= 64);
+            let page: Vec<String> = serde_json::from_str(&response.body).unwrap();
+            members.extend(page);
+            if response.status.code == 200 {
+                break;
+            }
+            assert_eq!(response.status.code, 206);
+        }
+
+        assert_eq!(members.len(), 20);
+        assert_eq!(members[19], "tag-19");
+        assert_eq!(send(&mut handler, "smembers tags x").status.code, 400);
     }
 
     #[test]
-    fn bad_get() {
+    fn set_commands() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "get".to_string(),
-            params: vec!["my-bad-key".to_string()],
-        };
-        let response = handler.handle_request(request);
+        let response = send(&mut handler, "sadd tags red blue red");
+        assert_eq!(response.body, "2");
+        let response = send(&mut handler, "sadd tags green");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "scard tags");
+        assert_eq!(response.body, "3");
+        let response = send(&mut handler, "sismember tags blue");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "sismember tags pink");
+        assert_eq!(response.body, "0");
+        let response = send(&mut handler, "srem tags blue pink");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "smembers tags");
+        assert_eq!(response.body, r#"["green","red"]"#);
+        let response = send(&mut handler, "type tags");
+        assert_eq!(response.body, "set");
+
+        let response = send(&mut handler, "lpush tags j1");
+        assert_eq!(response.status.code, 409);
+    }
+
+    #[test]
+    fn zset_commands() {
+        let mut handler = create_handler();
+        let response = send(&mut handler, "zadd board 10 alice 5 bob 7.5 carol");
+        assert_eq!(response.body, "3");
+        let response = send(&mut handler, "zadd board 12 bob");
+        assert_eq!(response.body, "0");
+        let response = send(&mut handler, "zrange board 0 -1");
+        assert_eq!(response.body, r#"["carol","alice","bob"]"#);
+        let response = send(&mut handler, "zrank board alice");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "zscore board carol");
+        assert_eq!(response.body, "7.5");
+        let response = send(&mut handler, "zincrby board 5 carol");
+        assert_eq!(response.body, "12.5");
+        let response = send(&mut handler, "zrange board -1 -1");
+        assert_eq!(response.body, r#"["carol"]"#);
+        let response = send(&mut handler, "type board");
+        assert_eq!(response.body, "zset");
+
+        let response = send(&mut handler, "zscore board dave");
         assert_eq!(response.status.code, 404);
+        let response = send(&mut handler, "zadd board ten dave");
+        assert_eq!(response.status.code, 400);
+        let response = send(&mut handler, "zadd board 1");
+        assert_eq!(response.status.code, 400);
     }
 
     #[test]
-    fn del() {
+    fn set_zset_savedb_loaddb() {
+        let filename = "tests/sets-out.kv";
         let mut handler = create_handler();
-        let rq = Request {
-            cmd: "set".to_string(),
-            params: vec!["mykey".to_string(), "my value".to_string()],
-        };
-        let _ = handler.handle_request(rq);
+        send(&mut handler, "sadd tags red blue");
+        send(&mut handler, "zadd board 10 alice 5 bob");
+        send(&mut handler, &format!("savedb {}", filename));
 
-        assert_eq!(handler.db.dbsize(), 1);
+        let mut handler = create_handler();
+        send(&mut handler, &format!("loaddb {}", filename));
+        let response = send(&mut handler, "scard tags");
+        assert_eq!(response.body, "2");
+        let response = send(&mut handler, "zrange board 0 -1");
+        assert_eq!(response.body, r#"["bob","alice"]"#);
+        let _ = std::fs::remove_file(filename);
+    }
 
-        let request = Request {
-            cmd: "del".to_string(),
-            params: vec!["mykey".to_string()],
-        };
-        let response = handler.handle_request(request.clone());
+    #[test]
+    fn publish_subscribe() {
+        let mut handler = create_handler();
+        let sub1: SocketAddr = "127.0.0.1:9011".parse().unwrap();
+        let sub2: SocketAddr = "127.0.0.1:9012".parse().unwrap();
+
+        let request = Request::from_message("subscribe config").unwrap();
+        let response = handler.handle_client_request(sub1, request.clone());
+        assert_eq!(response.as_u64().unwrap(), DEFAULT_LEASE);
+        handler.handle_client_request(sub2, request);
+
+        let response = send(&mut handler, "publish config k1 changed");
+        assert_eq!(response.body, "2");
+        let messages = handler.drain_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], (sub1, "message:config:k1 changed".to_string()));
+        assert!(handler.drain_messages().is_empty());
+
+        let request = Request::from_message("unsubscribe config").unwrap();
+        let response = handler.handle_client_request(sub1, request);
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "publish config again");
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, "publish other nobody");
+        assert_eq!(response.body, "0");
+
+        let response = send(&mut handler, "subscribe config");
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn watch_notifications() {
+        let mut handler = create_handler();
+        let watcher: SocketAddr = "127.0.0.1:9021".parse().unwrap();
+        let request = Request::from_message("watch config.*").unwrap();
+        let response = handler.handle_client_request(watcher, request);
+        assert_eq!(response.as_u64().unwrap(), DEFAULT_LEASE);
+        let request = Request::from_message("watch k1").unwrap();
+        handler.handle_client_request(watcher, request);
+
+        send(&mut handler, "set config.port 2200");
+        send(&mut handler, "set other value");
+        send(&mut handler, "hset k1 field value");
+        send(&mut handler, "del config.port");
+        let messages: Vec<String> = handler.drain_messages().into_iter().map(|m| m.1).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "notify:default:set:config.port",
+                "notify:default:set:k1",
+                "notify:default:del:config.port",
+            ]
+        );
+
+        let request = Request::from_message("unwatch k1").unwrap();
+        let response = handler.handle_client_request(watcher, request);
+        assert_eq!(response.body, "1");
+        send(&mut handler, "set k1 changed");
+        assert!(handler.drain_messages().is_empty());
+
+        let response = send(&mut handler, "watchers");
+        assert!(response.body.contains("default:config.*:127.0.0.1:9021"));
+        let request = Request::from_message("watchers").unwrap();
+        let response = handler.handle_client_request(watcher, request);
+        assert_eq!(response.status.code, 403);
+    }
+
+    #[test]
+    fn max_subscribers() {
+        let mut handler = create_handler();
+        handler.max_subscribers = 1;
+        let subscriber: SocketAddr = "127.0.0.1:9023".parse().unwrap();
+        let request = Request::from_message("subscribe news").unwrap();
+        let response = handler.handle_client_request(subscriber, request.clone());
         assert_eq!(response.status.code, 200);
-        let response = handler.handle_request(request.clone());
+        let response = handler.handle_client_request(subscriber, request);
+        assert_eq!(response.status.code, 200);
+
+        let other: SocketAddr = "127.0.0.1:9024".parse().unwrap();
+        let request = Request::from_message("subscribe news").unwrap();
+        let response = handler.handle_client_request(other, request);
+        assert_eq!(response.as_string(), "429:too-many:news");
+        assert_eq!(send(&mut handler, "publish news hello").body, "1");
+
+        send(&mut handler, "config set max_subscribers 2");
+        let request = Request::from_message("subscribe news").unwrap();
+        let response = handler.handle_client_request(other, request);
         assert_eq!(response.status.code, 200);
     }
 
     #[test]
-    fn keys() {
+    fn max_watchers() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "keys".to_string(),
-            params: vec![],
-        };
-        let response = handler.handle_request(request);
+        handler.max_watchers = 1;
+        let watcher: SocketAddr = "127.0.0.1:9022".parse().unwrap();
+        let request = Request::from_message("watch k1").unwrap();
+        let response = handler.handle_client_request(watcher, request.clone());
+        assert_eq!(response.status.code, 200);
+        let response = handler.handle_client_request(watcher, request);
+        assert_eq!(response.status.code, 200);
+
+        let request = Request::from_message("watch k2").unwrap();
+        let response = handler.handle_client_request(watcher, request.clone());
+        assert_eq!(response.status.code, 429);
+
+        // an expired watch frees its place
+        handler.watches = Subscriptions::create(1);
+        let request_k1 = Request::from_message("watch k1").unwrap();
+        handler.handle_client_request(watcher, request_k1);
+        std::thread::sleep(Duration::from_millis(1100));
+        let response = handler.handle_client_request(watcher, request);
         assert_eq!(response.status.code, 200);
     }
 
     #[test]
-    fn loaddb() {
+    fn exec_commands() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "loaddb".to_string(),
-            params: vec!["tests/users-ref.kv".to_string()],
-        };
-        let response = handler.handle_request(request);
+        let response = send(
+            &mut handler,
+            r#"exec [["set","k1","one"],["rpush","jobs","j1","j2"],["get","k1"],["get","nokey"]]"#,
+        );
         assert_eq!(response.status.code, 200);
+        let responses: Vec<String> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(
+            responses,
+            vec!["200:ok:ok", "200:ok:2", "200:ok:one", "404:not-found:nokey"]
+        );
     }
 
     #[test]
-    fn loaddb_bad() {
+    fn exec_words_with_spaces() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "loaddb".to_string(),
-            params: vec!["badfil/users-ref.kv".to_string()],
-        };
-        let response = handler.handle_request(request);
+        let response = send(
+            &mut handler,
+            r#"exec [["set","my key","a spaced value"],["get","my key"],["hset","h","f","v w"]]"#,
+        );
+        assert_eq!(response.status.code, 200);
+        let responses: Vec<String> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(
+            responses,
+            vec!["200:ok:ok", "200:ok:a spaced value", "200:ok:1"]
+        );
+        assert_eq!(handler.db().get("my key"), Some(b"a spaced value".to_vec()));
+        assert_eq!(send(&mut handler, "hget h f").body, "v w");
+
+        // a failed exec restores the key with the space
+        let response = send(
+            &mut handler,
+            r#"exec [["set","my key","changed"],["lpush","h","j1"]]"#,
+        );
+        assert_eq!(response.status.code, 409);
+        assert_eq!(handler.db().get("my key"), Some(b"a spaced value".to_vec()));
+    }
+
+    #[test]
+    fn exec_rollback() {
+        let mut handler = create_handler();
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "set plain value");
+        let version = send(&mut handler, "version k1").as_u64().unwrap();
+
+        let response = send(
+            &mut handler,
+            r#"exec [["set","k1","two"],["set","k2","new"],["del","k1"],["lpush","plain","j1"]]"#,
+        );
+        assert_eq!(response.status.code, 409);
+        let responses: Vec<String> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(responses.len(), 4);
+
+        assert_eq!(send(&mut handler, "get k1").body, "one");
+        assert_eq!(send(&mut handler, "get k2").status.code, 404);
+        assert_eq!(send(&mut handler, "version k1").as_u64().unwrap(), version);
+
+        let response = send(&mut handler, r#"exec [["set","k2","new"],["flushdb"]]"#);
+        assert_eq!(response.status.code, 400);
+        assert_eq!(send(&mut handler, "get k2").status.code, 404);
+
+        let response = send(&mut handler, "exec not json");
         assert_eq!(response.status.code, 400);
     }
 
     #[test]
-    fn savedb() {
+    fn exec_ifversion() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "savedb".to_string(),
-            params: vec!["tests/test-out.kv".to_string()],
-        };
-        let response = handler.handle_request(request);
+        send(&mut handler, "set k1 one");
+        let version = send(&mut handler, "version k1").body;
+        assert_ne!(version, "0");
+
+        let msg = format!(
+            r#"exec [["ifversion","k1","{}"],["set","k1","two"]]"#,
+            version
+        );
+        let response = send(&mut handler, &msg);
+        assert_eq!(response.status.code, 200);
+        assert_ne!(send(&mut handler, "version k1").body, version);
+
+        // the version changed so the same exec fails and nothing is applied
+        let msg = format!(
+            r#"exec [["ifversion","k1","{}"],["set","k1","three"]]"#,
+            version
+        );
+        let response = send(&mut handler, &msg);
+        assert_eq!(response.status.code, 412);
+        assert_eq!(send(&mut handler, "get k1").body, "two");
+
+        // version 0 checks that the key does not exist
+        let response = send(
+            &mut handler,
+            r#"exec [["ifversion","k9","0"],["set","k9","new"]]"#,
+        );
         assert_eq!(response.status.code, 200);
     }
 
     #[test]
-    fn savedb_bad() {
+    fn versions_after_loaddb() {
+        let mut handler = create_handler_with(|config| config.key_history = Some(2));
+        send(&mut handler, "set other value");
+        send(&mut handler, "set u100 old");
+        let other = handler.version("other");
+
+        send(&mut handler, "loaddb tests/users-ref.kv");
+        let response = send(&mut handler, "version u100");
+        assert!(response.as_u64().unwrap() > other);
+        let response = send(&mut handler, "version nokey");
+        assert_eq!(response.body, "0");
+
+        // keys the file doesn't change keep their version, and changed ones get history
+        assert_eq!(handler.version("other"), other);
+        let response = send(&mut handler, "history u100");
+        assert!(response.body.contains(r#""value":"old""#));
+        let u101 = handler.version("u101");
+        send(&mut handler, "loaddb tests/users-ref.kv");
+        assert_eq!(handler.version("u101"), u101);
+    }
+
+    #[test]
+    fn getmeta_history_revert() {
+        let mut handler = create_handler_with(|config| config.key_history = Some(2));
+
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "set k1 two");
+        send(&mut handler, "set k1 three");
+        send(&mut handler, "set k1 four");
+
+        let response = send(&mut handler, "getmeta k1");
+        let meta: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(meta["version"].as_u64().unwrap(), handler.version("k1"));
+        assert_eq!(meta["type"], "string");
+        assert_eq!(meta["size"], 4);
+        assert_eq!(meta["history"], 2);
+        assert_eq!(send(&mut handler, "getmeta nokey").status.code, 404);
+
+        let response = send(&mut handler, "history k1");
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        let values: Vec<&str> = entries
+            .iter()
+            .map(|e| e["value"].as_str().unwrap())
+            .collect();
+        assert_eq!(values, vec!["three", "two"]);
+        let response = send(&mut handler, "history k1 1");
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(entries.len(), 1);
+
+        // a deleted key keeps its history and can be reverted
+        send(&mut handler, "del k1");
+        let version = entries[0]["version"].as_u64().unwrap();
+        let response = send(&mut handler, &format!("revert k1 {}", version));
+        assert_eq!(response.status.code, 200);
+        assert_eq!(response.as_u64().unwrap(), handler.version("k1"));
+        assert_eq!(send(&mut handler, "get k1").body, "three");
+
+        assert_eq!(send(&mut handler, "revert k1 99999").status.code, 404);
+        assert_eq!(send(&mut handler, "revert k1 abc").status.code, 400);
+
+        // a long history is paged like lrange
+        handler.max_payload = 100;
+        let response = send(&mut handler, "history k1");
+        assert_eq!(response.status.code, 206);
+        let page: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        let response = send(&mut handler, &format!("history k1 2 {}", page.len()));
+        assert_eq!(response.status.code, 200);
+        let rest: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(page.len() + rest.len(), 2);
+        assert_eq!(send(&mut handler, "history k1 2 x").status.code, 400);
+    }
+
+    #[test]
+    fn history_off_by_default() {
         let mut handler = create_handler();
-        let request = Request {
-            cmd: "savedb".to_string(),
-            params: vec!["not-a-good-file/test-out.kv".to_string()],
-        };
-        let response = handler.handle_request(request);
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "set k1 two");
+        assert_eq!(send(&mut handler, "history k1").body, "[]");
+        send(&mut handler, "del k1");
+        assert_eq!(send(&mut handler, "getmeta k1").status.code, 404);
+    }
+
+    #[test]
+    fn eval_script() {
+        let mut handler = create_handler();
+        send(&mut handler, "set a on");
+
+        let response = send(
+            &mut handler,
+            r#"eval (if (== (get "a") "on") (set "b" "yes") "skipped")"#,
+        );
+        assert_eq!(response.as_string(), "200:ok:ok");
+        assert_eq!(send(&mut handler, "get b").body, "yes");
+
+        let response = send(&mut handler, r#"eval (rpush "jobs" "j1") (llen "jobs")"#);
+        assert_eq!(response.body, "1");
+        let response = send(&mut handler, r#"eval (nil? (get "nokey"))"#);
+        assert_eq!(response.body, "true");
+    }
+
+    #[test]
+    fn eval_words_with_spaces() {
+        let mut handler = create_handler();
+        let response = send(
+            &mut handler,
+            r#"eval (set "my key" "a spaced value") (get "my key")"#,
+        );
+        assert_eq!(response.body, "a spaced value");
+        assert_eq!(handler.db().get("my key"), Some(b"a spaced value".to_vec()));
+        assert_eq!(handler.db().get("my"), None);
+    }
+
+    #[test]
+    fn eval_rollback() {
+        let mut handler = create_handler();
+        send(&mut handler, "set a on");
+
+        // the wrong type fails the script and the set is rolled back
+        let response = send(&mut handler, r#"eval (set "b" "yes") (lpush "a" "j1")"#);
+        assert_eq!(response.status.code, 409);
+        assert_eq!(send(&mut handler, "get b").status.code, 404);
+
+        let response = send(&mut handler, r#"eval (set "b" "yes") (while true 1)"#);
+        assert_eq!(response.status.code, 429);
+        assert_eq!(send(&mut handler, "get b").status.code, 404);
+
+        let script = r#"(set "b" "yes") (let s "x") (while true (let s (concat s s)))"#;
+        let response = send(&mut handler, &format!("eval {}", script));
+        assert_eq!(response.as_string(), "429:too-many:text limit 65536");
+        assert_eq!(send(&mut handler, "get b").status.code, 404);
+
+        let response = send(&mut handler, r#"eval (set "b" "yes") (flushdb)"#);
+        assert_eq!(response.status.code, 400);
+        assert_eq!(send(&mut handler, "get a").body, "on");
+
+        let response = send(&mut handler, r#"eval (get "a""#);
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn noeviction_refuses_writes() {
+        // each key is about 2 + 5 + 48 bytes and a write must fit before it runs
+        let mut handler = create_handler_with(|config| {
+            config.max_memory = Some(120);
+            config.eviction_policy = Some("noeviction".to_string());
+        });
+        assert_eq!(send(&mut handler, "set k1 value").status.code, 200);
+        assert_eq!(send(&mut handler, "set k2 value").status.code, 200);
+        let response = send(&mut handler, "set k3 value");
+        assert_eq!(response.status.code, 507);
+
+        // reads and deletes still work and free memory
+        assert_eq!(send(&mut handler, "get k1").body, "value");
+        send(&mut handler, "del k1");
+        assert_eq!(send(&mut handler, "set k3 value").status.code, 200);
+        assert!(send(&mut handler, "status")
+            .body
+            .contains("rejected_writes: 1"));
+    }
+
+    #[test]
+    fn exec_and_eval_make_room() {
+        let mut handler = create_handler_with(|config| {
+            config.max_memory = Some(120);
+            config.eviction_policy = Some("noeviction".to_string());
+        });
+        send(&mut handler, "set k1 value");
+
+        // each command inside is checked and the whole batch rolls back
+        let response = send(
+            &mut handler,
+            r#"exec [["set","k2","value"],["set","k3","value"]]"#,
+        );
+        assert_eq!(response.status.code, 507);
+        assert_eq!(send(&mut handler, "get k2").status.code, 404);
+
+        let response = send(&mut handler, r#"eval (while true (rpush "q" "value"))"#);
+        assert_eq!(response.as_string(), "507:out-of-memory:rpush");
+        assert_eq!(send(&mut handler, "exists q").body, "0");
+        assert_eq!(handler.dbsize(), 1);
+
+        let response = send(&mut handler, r#"exec [["set","k2","value"]]"#);
+        assert_eq!(response.status.code, 200);
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut handler = create_handler_with(|config| {
+            config.max_memory = Some(120);
+            config.eviction_policy = Some("allkeys-lru".to_string());
+        });
+        send(&mut handler, "set k1 value");
+        send(&mut handler, "set k2 value");
+        send(&mut handler, "get k1");
+
+        assert_eq!(send(&mut handler, "set k3 value").status.code, 200);
+        assert_eq!(send(&mut handler, "get k2").status.code, 404);
+        assert_eq!(send(&mut handler, "get k1").body, "value");
+        assert!(send(&mut handler, "status")
+            .body
+            .contains("evicted_keys: 1"));
+
+        // a write that could never fit is refused without evicting anything
+        let value = "v".repeat(120);
+        let response = send(&mut handler, &format!("set k4 {}", value));
+        assert_eq!(response.status.code, 507);
+        assert_eq!(send(&mut handler, "get k1").body, "value");
+        assert_eq!(send(&mut handler, "get k3").body, "value");
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut handler = create_handler_with(|config| {
+            config.max_key_length = Some(8);
+            config.max_value_length = Some(10);
+            config.max_keys = Some(2);
+        });
+
+        let response = send(&mut handler, "set a-very-long-key value");
+        assert_eq!(response.status.code, 414);
+        let response = send(&mut handler, "set k1 a value that is too long");
+        assert_eq!(response.status.code, 415);
+
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "set k2 two");
+        let response = send(&mut handler, "set k3 three");
+        assert_eq!(response.as_string(), "416:too-many-keys:k3");
+        // updating an existing key is not a new key
+        assert_eq!(send(&mut handler, "set k2 two-2").status.code, 200);
+
+        // a file over the limits loads nothing
+        let response = send(&mut handler, "loaddb tests/users-ref.kv");
+        assert_eq!(response.status.code, 415);
+        let response = send(&mut handler, "loaddb --replace tests/users-ref.kv");
+        assert_eq!(response.status.code, 415);
+        assert_eq!(handler.dbsize(), 2);
+    }
+
+    #[test]
+    fn size_limits_on_every_write() {
+        let config = test_config(|config| {
+            config.max_key_length = Some(8);
+            config.max_value_length = Some(30);
+            config.max_keys = Some(2);
+        });
+        let mut handler = Handler::create(DataStore::create(), &config);
+
+        // the limit is on the encoded value, e.g. @hash:{"f":"v"}
+        assert_eq!(send(&mut handler, "hset h f v").status.code, 200);
+        let response = send(&mut handler, "hset h f a-value-that-grows-the-hash");
+        assert_eq!(response.as_string(), "415:value-too-long:h");
+        assert_eq!(send(&mut handler, "hget h f").body, "v");
+
+        assert_eq!(send(&mut handler, "rpush l a b").status.code, 200);
+        let response = send(&mut handler, "rpush l c d e f g h");
+        assert_eq!(response.status.code, 415);
+        assert_eq!(send(&mut handler, "llen l").body, "2");
+        let response = send(&mut handler, "rpush l2 a");
+        assert_eq!(response.as_string(), "416:too-many-keys:l2");
+        let response = send(&mut handler, "sadd a-very-long-key m");
+        assert_eq!(response.status.code, 414);
+
+        let response = send(&mut handler, "copy h h2");
+        assert_eq!(response.as_string(), "416:too-many-keys:h2");
+        send(&mut handler, "del l");
+        let response = send(&mut handler, "copy h a-very-long-key");
+        assert_eq!(response.status.code, 414);
+        let response = send(&mut handler, "rename h a-very-long-key");
+        assert_eq!(response.status.code, 414);
+        assert_eq!(send(&mut handler, "copy h h2").status.code, 200);
+
+        // a data file over the limits is not loaded at startup
+        let db = DataStore::create();
+        db.loaddb("tests/users-ref.kv").unwrap();
+        let handler = Handler::create(db, &config);
+        assert_eq!(handler.dbsize(), 0);
+    }
+
+    #[test]
+    fn info_sections() {
+        let mut handler = create_handler();
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "get k1");
+
+        let response = send(&mut handler, "info");
+        assert_eq!(response.status.code, 200);
+        let info: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        for section in INFO_SECTIONS {
+            assert!(info.get(section).is_some(), "{}", section);
+        }
+        assert_eq!(info["server"]["version"], crate::VERSION);
+        assert_eq!(info["keyspace"]["default"]["keys"], 1);
+        assert_eq!(info["commandstats"]["get"], 1);
+
+        let response = send(&mut handler, "info memory");
+        let info: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(info["memory"]["eviction_policy"], "noeviction");
+        assert!(info.get("server").is_none());
+
+        send(&mut handler, "flarberr");
+        let response = send(&mut handler, "info commandstats");
+        assert!(response.body.contains(r#""unknown":1"#));
+        assert_eq!(send(&mut handler, "info nope").status.code, 400);
+
+        // sections that don't fit are left off and the client asks for them one at a time
+        handler.max_payload = 200;
+        let response = send(&mut handler, "info");
+        assert_eq!(response.status.code, 206);
+        assert!(response.as_string().len() <= 200);
+    }
+
+    #[test]
+    fn command_stats() {
+        let mut handler = create_handler();
+        send(&mut handler, "set k1 one");
+        send(&mut handler, "get k1");
+        send(&mut handler, "get nokey");
+        send(&mut handler, "lpush k1 j1");
+
+        let response = send(&mut handler, "stats get");
+        let stats: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(stats["get"]["calls"], 2);
+        assert_eq!(stats["get"]["not_found"], 1);
+        assert!(stats["get"]["p99_us"].as_u64().unwrap() >= 1);
+
+        let response = send(&mut handler, "stats");
+        let stats: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(stats["lpush"]["errors"], 1);
+        assert_eq!(stats["set"]["calls"], 1);
+        assert_eq!(send(&mut handler, "stats zadd").status.code, 404);
+
+        assert_eq!(send(&mut handler, "stats reset").body, "ok");
+        assert_eq!(send(&mut handler, "stats get").status.code, 404);
+    }
+
+    #[test]
+    fn slowlog_commands() {
+        let mut handler = create_handler();
+        handler.slowlog = SlowLog::create(1000, 10);
+        handler.record_slow(None, "keys", Duration::from_micros(10));
+        handler.record_slow(None, "keys", Duration::from_millis(20));
+        handler.record_slow(None, "savedb data/users.kv", Duration::from_millis(30));
+
+        assert_eq!(send(&mut handler, "slowlog len").body, "2");
+        let response = send(&mut handler, "slowlog get 1");
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["command"], "savedb");
+        assert_eq!(entries[0]["duration_us"], 30000);
+
+        let response = send(&mut handler, "slowlog get");
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(send(&mut handler, "slowlog get x").status.code, 400);
+        assert_eq!(send(&mut handler, "slowlog").status.code, 400);
+        let client = SocketAddr::from(([127, 0, 0, 1], 9103));
+        let request = Request::from_message("slowlog get").unwrap();
+        let response = handler.handle_client_request(client, request);
+        assert_eq!(response.status.code, 403);
+
+        assert_eq!(send(&mut handler, "slowlog reset").body, "ok");
+        assert_eq!(send(&mut handler, "slowlog len").body, "0");
+
+        // the values are not kept
+        let msg = r#"exec [["set","k1","secret"]]"#;
+        handler.record_slow(None, msg, Duration::from_millis(30));
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_str(&send(&mut handler, "slowlog get").body).unwrap();
+        assert_eq!(entries[0]["params"], r#"[["set","k1","(redacted)"]]"#);
+    }
+
+    #[test]
+    fn monitor_stream() {
+        let mut handler = create_handler();
+        let monitor = SocketAddr::from(([127, 0, 0, 1], 9101));
+        let other = SocketAddr::from(([127, 0, 0, 1], 9102));
+
+        // admin only, and a client without auth is refused
+        let request = Request::from_message("monitor").unwrap();
+        let response = handler.handle_client_request(monitor, request);
+        assert_eq!(response.status.code, 403);
+
+        handler.admins.insert(monitor);
+        let request = Request::from_message("monitor").unwrap();
+        let response = handler.handle_client_request(monitor, request);
+        assert_eq!(response.body, DEFAULT_LEASE.to_string());
+        assert!(handler.drain_messages().is_empty());
+
+        let request = Request::from_message("set k1 one").unwrap();
+        handler.handle_client_request(other, request);
+        let request = Request::from_message("auth secret-key").unwrap();
+        handler.handle_client_request(other, request);
+
+        let messages = handler.drain_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].0, monitor);
+        assert!(messages[0].1.starts_with("monitor:"));
+        assert!(messages[0]
+            .1
+            .ends_with("[default 127.0.0.1:9102] set k1 (redacted)"));
+        assert!(messages[1].1.ends_with("auth (redacted)"));
+
+        // values are shown only when they are logged
+        handler.log_values = true;
+        send(&mut handler, "set k1 two");
+        let messages = handler.drain_messages();
+        assert!(messages[0].1.ends_with("[default local] set k1 two"));
+
+        let request = Request::from_message("unmonitor").unwrap();
+        let response = handler.handle_client_request(monitor, request);
+        assert_eq!(response.body, "1");
+        handler.drain_messages();
+        send(&mut handler, "get k1");
+        assert!(handler.drain_messages().is_empty());
+    }
+
+    #[test]
+    fn config_reload() {
+        let filename = "tests/reload-config-out.toml";
+        let text = std::fs::read_to_string("tests/server-config.toml").unwrap();
+        std::fs::write(filename, &text).unwrap();
+        let config = Config::read_config(filename).unwrap();
+        let mut handler = Handler::create(DataStore::create(), &config);
+        let client = SocketAddr::from(([127, 0, 0, 1], 9201));
+
+        let request = Request::from_message("config reload").unwrap();
+        let response = handler.handle_client_request(client, request);
+        assert_eq!(response.status.code, 403);
+
+        let response = send(&mut handler, "config reload");
+        assert_eq!(response.body, r#"{"reloaded":[],"restart":[]}"#);
+
+        let text = format!("{}\nmax_keys = 1\nadmin_keys = [\"secret\"]\n", text)
+            .replace("port = 28400", "port = 28401");
+        std::fs::write(filename, text).unwrap();
+        handler.admins.insert(client);
+        let request = Request::from_message("config reload").unwrap();
+        let response = handler.handle_client_request(client, request);
+        assert_eq!(
+            response.body,
+            r#"{"reloaded":["admin_keys","max_keys"],"restart":["port"]}"#
+        );
+        assert!(handler.admins.is_empty());
+        assert_eq!(handler.admin_keys, vec!["secret".to_string()]);
+        assert_eq!(handler.config.port, 28400);
+
+        send(&mut handler, "set k1 one");
+        let response = send(&mut handler, "set k2 two");
+        assert_eq!(response.status.code, 416);
+
+        // problems in the settings that need a restart or unknown keys are only warnings
+        let text = std::fs::read_to_string(filename)
+            .unwrap()
+            .replace("port = 28401", "port = 0\nmax_memroy = 1");
+        std::fs::write(filename, &text).unwrap();
+        let response = send(&mut handler, "config reload");
+        assert_eq!(response.status.code, 200, "{}", response.body);
+        assert_eq!(handler.config.port, 28400);
+
+        std::fs::write(
+            filename,
+            format!("{}\neviction_policy = \"random\"\n", text),
+        )
+        .unwrap();
+        let response = send(&mut handler, "config reload");
+        assert_eq!(response.status.code, 400);
+        assert_eq!(response.body, "unknown eviction policy: random");
+
+        std::fs::write(filename, "port = flarb").unwrap();
+        let response = send(&mut handler, "config reload");
+        assert_eq!(response.status.code, 400);
+        assert!(response.body.starts_with("bad config"));
+        std::fs::remove_file(filename).unwrap();
+
+        let response = send(&mut handler, "config flarb");
+        assert_eq!(response.status.code, 400);
+    }
+
+    #[test]
+    fn config_get_set_rewrite() {
+        let filename = "tests/config-set-out.toml";
+        std::fs::copy("tests/server-config.toml", filename).unwrap();
+        let config = Config::read_config(filename).unwrap();
+        let mut handler = Handler::create(DataStore::create(), &config);
+
+        let client = SocketAddr::from(([127, 0, 0, 1], 9202));
+        let request = Request::from_message("config get port").unwrap();
+        let response = handler.handle_client_request(client, request);
+        assert_eq!(response.status.code, 403);
+
+        let response = send(&mut handler, "config get port");
+        assert_eq!(response.body, "28400");
+        let response = send(&mut handler, "config get max_value_length");
+        assert_eq!(response.status.code, 404);
+        let response = send(&mut handler, "config get flarb");
+        assert_eq!(response.body, "unknown setting: flarb");
+
+        let response = send(&mut handler, "config set max_value_length 4");
+        assert_eq!(response.body, "ok");
+        let response = send(&mut handler, "set k1 12345");
+        assert_eq!(response.status.code, 415);
+        let response = send(&mut handler, "config get max_value_length");
+        assert_eq!(response.body, "4");
+
+        send(&mut handler, "config set slowlog_max_len 0");
+        handler.record_slow(None, "keys", Duration::from_secs(1));
+        assert!(handler.slowlog.is_empty());
+
+        let response = send(&mut handler, "config set log_level warn");
+        assert_eq!(response.body, "ok");
+        let response = send(&mut handler, "config set port 22300");
+        assert_eq!(response.body, "port can't be set while running");
+        let response = send(&mut handler, "config set max_keys");
         assert_eq!(response.status.code, 400);
+
+        let response = send(&mut handler, "config rewrite");
+        assert_eq!(response.body, filename);
+        let written = Config::read_config(filename).unwrap();
+        assert_eq!(written.max_value_length, Some(4));
+        assert_eq!(written.log_level, Some("warn".to_string()));
+
+        // a reload keeps the running port, but the rewrite keeps the port in the file
+        let text = std::fs::read_to_string(filename)
+            .unwrap()
+            .replace("port = 28400", "port = 28401");
+        std::fs::write(filename, text).unwrap();
+        send(&mut handler, "config reload");
+        assert_eq!(handler.config.port, 28400);
+        let response = send(&mut handler, "config rewrite");
+        assert_eq!(response.body, filename);
+        let written = Config::read_config(filename).unwrap();
+        assert_eq!(written.port, 28401);
+        assert_eq!(written.max_value_length, Some(4));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn mutating_commands() {
+        assert!(Handler::is_mutating("set"));
+        assert!(Handler::is_mutating("config:del"));
+        assert!(!Handler::is_mutating("get"));
+        assert!(!Handler::is_mutating("auth"));
+    }
+
+    #[test]
+    fn command_table() {
+        let names: HashSet<&str> = COMMAND_TABLE.iter().map(|c| c.name).collect();
+        assert_eq!(names.len(), COMMAND_TABLE.len());
+
+        // every command that needs memory changes data
+        for command in COMMAND_TABLE {
+            assert!(
+                !command.grows_memory() || command.writes(),
+                "{}",
+                command.name
+            );
+        }
+        assert!(Command::find("hset").is_some_and(Command::exec_allowed));
+        assert!(!Command::find("flushdb").is_some_and(Command::exec_allowed));
+        assert!(Command::find("nope").is_none());
     }
 
     #[test]
@@ -377,6 +3868,13 @@ mod tests {
         };
         let response = handler.handle_request(request);
         assert_eq!(response.status.code, 400);
+        assert!(send(&mut handler, "stats").body.contains("unknown"));
+
+        // every command in the table runs, even without params
+        for command in COMMAND_TABLE {
+            let response = send(&mut handler, command.name);
+            assert_ne!(response.status.code, 0, "{}", command.name);
+        }
     }
 
     #[test]
@@ -384,7 +3882,7 @@ mod tests {
         let db = DataStore::create();
         let handler = Handler::new(db);
 
-        assert_eq!(handler.db.dbsize(), 0);
+        assert_eq!(handler.db().dbsize(), 0);
     }
 
     #[test]
@@ -1,11 +1,22 @@
 ///
 /// the modules
 ///
+pub mod access;
 pub mod client;
 pub mod config;
+pub mod eviction;
 pub mod handler;
+pub mod meta;
+pub mod metrics;
 pub mod parsers;
+pub mod pubsub;
+pub mod script;
 pub mod server;
+pub mod slowlog;
+pub mod snapshot;
+pub mod stats;
+pub mod trace;
+pub mod values;
 
 /// the current app version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,226 @@
+/// per-key version, last-modified time and an optional bounded history of prior values
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub version: u64,
+    pub modified: u64,
+    #[serde(serialize_with = "as_text")]
+    pub value: Vec<u8>,
+}
+
+/// the number of deleted keys per database whose history is kept for a revert
+pub const MAX_DELETED: usize = 1000;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KeyMeta {
+    /// zero when the key has been deleted
+    pub version: u64,
+    pub modified: u64,
+    /// prior values, newest first
+    pub history: VecDeque<HistoryEntry>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Metadata {
+    databases: HashMap<String, HashMap<String, KeyMeta>>,
+    last_version: u64,
+    history: usize,
+    /// the deleted keys with history in each database, oldest first
+    deleted: HashMap<String, VecDeque<String>>,
+    max_deleted: usize,
+}
+
+impl Metadata {
+    /// create the metadata keeping up to history prior values per key
+    pub fn create(history: usize) -> Metadata {
+        Metadata {
+            databases: HashMap::new(),
+            last_version: 0,
+            history,
+            deleted: HashMap::new(),
+            max_deleted: MAX_DELETED,
+        }
+    }
+
+    /// the metadata for the key in the database
+    pub fn get(&self, db: &str, key: &str) -> Option<&KeyMeta> {
+        self.databases.get(db).and_then(|keys| keys.get(key))
+    }
+
+    /// the key's version; zero if the key does not exist
+    pub fn version(&self, db: &str, key: &str) -> u64 {
+        self.get(db, key).map_or(0, |meta| meta.version)
+    }
+
+    /// record a change to the key with the value it had before, if any
+    pub fn changed(&mut self, db: &str, key: &str, previous: Option<Vec<u8>>, deleted: bool) {
+        let keys = self.databases.entry(db.to_string()).or_default();
+        let meta = keys.entry(key.to_string()).or_default();
+
+        if let Some(previous) = previous.filter(|_| self.history > 0) {
+            meta.history.push_front(HistoryEntry {
+                version: meta.version,
+                modified: meta.modified,
+                value: previous,
+            });
+            meta.history.truncate(self.history);
+        }
+
+        meta.modified = get_ts();
+        if deleted {
+            meta.version = 0;
+            if meta.history.is_empty() {
+                keys.remove(key);
+            } else {
+                // a key deleted again moves to the back
+                if let Some(deleted) = self.deleted.get_mut(db) {
+                    deleted.retain(|k| k != key);
+                }
+                self.deleted(db, vec![key.to_string()]);
+            }
+        } else {
+            self.last_version += 1;
+            meta.version = self.last_version;
+        }
+    }
+
+    /// give each key a new version after a bulk change and drop the keys no longer in the database
+    pub fn reset(&mut self, db: &str, keys: Vec<String>) {
+        let now = get_ts();
+        let metas = self.databases.entry(db.to_string()).or_default();
+        for meta in metas.values_mut() {
+            meta.version = 0;
+        }
+
+        for key in keys {
+            self.last_version += 1;
+            let meta = metas.entry(key).or_default();
+            meta.version = self.last_version;
+            meta.modified = now;
+        }
+
+        metas.retain(|_, meta| meta.version > 0 || !meta.history.is_empty());
+        let deleted = metas
+            .iter()
+            .filter(|(_, meta)| meta.version == 0)
+            .map(|(key, _)| key.to_string())
+            .collect();
+        self.deleted(db, deleted);
+    }
+
+    /// track the deleted keys that kept their history; past the max, the history of the
+    /// oldest is dropped
+    fn deleted(&mut self, db: &str, mut keys: Vec<String>) {
+        let deleted = self.deleted.entry(db.to_string()).or_default();
+        let known: HashSet<String> = deleted.iter().cloned().collect();
+        keys.retain(|key| !known.contains(key));
+        keys.sort();
+        deleted.extend(keys);
+
+        let metas = self.databases.entry(db.to_string()).or_default();
+        while deleted.len() > self.max_deleted {
+            if let Some(oldest) = deleted.pop_front() {
+                if metas.get(&oldest).is_some_and(|meta| meta.version == 0) {
+                    metas.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// put back the metadata saved before a change, e.g. an exec rollback
+    pub fn put(&mut self, db: &str, key: &str, meta: Option<KeyMeta>) {
+        let keys = self.databases.entry(db.to_string()).or_default();
+        match meta {
+            Some(meta) => keys.insert(key.to_string(), meta),
+            None => keys.remove(key),
+        };
+    }
+}
+
+/// write a value as text, replacing bytes that are not utf-8
+fn as_text<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(value))
+}
+
+/// return the unix timestamp
+fn get_ts() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("seconds")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        let mut meta = Metadata::create(0);
+        assert_eq!(meta.version("default", "k1"), 0);
+
+        meta.changed("default", "k1", None, false);
+        let v1 = meta.version("default", "k1");
+        meta.changed("default", "k1", Some(b"one".to_vec()), false);
+        let v2 = meta.version("default", "k1");
+        assert!(v2 > v1);
+        assert!(meta.get("default", "k1").unwrap().history.is_empty());
+
+        meta.changed("default", "k1", Some(b"two".to_vec()), true);
+        assert_eq!(meta.version("default", "k1"), 0);
+        assert!(meta.get("default", "k1").is_none());
+        assert_eq!(meta.version("other", "k1"), 0);
+    }
+
+    #[test]
+    fn bounded_history() {
+        let mut meta = Metadata::create(2);
+        meta.changed("default", "k1", None, false);
+        meta.changed("default", "k1", Some(b"one".to_vec()), false);
+        meta.changed("default", "k1", Some(b"two".to_vec()), false);
+        meta.changed("default", "k1", Some(b"three".to_vec()), true);
+
+        let key = meta.get("default", "k1").unwrap();
+        assert_eq!(key.version, 0);
+        let values: Vec<&[u8]> = key.history.iter().map(|h| h.value.as_slice()).collect();
+        assert_eq!(values, vec![b"three".as_slice(), b"two"]);
+    }
+
+    #[test]
+    fn deleted_keys_capped() {
+        let mut meta = Metadata::create(2);
+        meta.max_deleted = 2;
+        for key in ["k1", "k2", "k3"] {
+            meta.changed("default", key, None, false);
+            meta.changed("default", key, Some(vec![0xff, b'v']), true);
+        }
+
+        assert!(meta.get("default", "k1").is_none());
+        assert!(meta.get("default", "k2").is_some());
+
+        // the keys dropped by a bulk change count too
+        meta.changed("default", "k4", None, false);
+        meta.changed("default", "k4", Some(b"one".to_vec()), false);
+        meta.reset("default", vec![]);
+        assert!(meta.get("default", "k2").is_none());
+        assert!(meta.get("default", "k4").is_some());
+        let entry = &meta.get("default", "k3").unwrap().history[0];
+        assert_eq!(entry.value, vec![0xff, b'v']);
+        let json = serde_json::to_value(entry).unwrap();
+        assert_eq!(json["value"], "\u{fffd}v");
+    }
+
+    #[test]
+    fn reset() {
+        let mut meta = Metadata::create(0);
+        meta.changed("default", "gone", None, false);
+        meta.reset("default", vec!["k1".to_string(), "k2".to_string()]);
+
+        assert!(meta.version("default", "k1") > 0);
+        assert!(meta.version("default", "k2") > 0);
+        assert_eq!(meta.version("default", "gone"), 0);
+    }
+}
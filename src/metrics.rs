@@ -0,0 +1,182 @@
+/// prometheus metrics and health checks served over a small embedded http listener
+///
+/// the handler is owned by the server loop, so a scrape sends a reply channel to the loop and
+/// waits for the rendered text; a loop that doesn't answer in time makes /readyz fail.
+use log::{info, warn};
+use std::fmt::Display;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+
+/// how long a request waits for the server loop before the reply is 503
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// the channel a scrape uses to get the metrics text from the server loop
+pub type Scrape = oneshot::Sender<String>;
+
+/// the prometheus text exposition format
+#[derive(Debug, Default, Clone)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    /// start a metric with its help and type, e.g. counter, gauge or histogram
+    pub fn metric(&mut self, name: &str, kind: &str, help: &str) {
+        self.text.push_str(&format!("# HELP {} {}\n", name, help));
+        self.text.push_str(&format!("# TYPE {} {}\n", name, kind));
+    }
+
+    /// add a sample with its labels
+    pub fn sample<T: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: T) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            self.text.push_str(&format!("{{{}}}", labels.join(",")));
+        }
+        self.text.push_str(&format!(" {}\n", value));
+    }
+
+    pub fn as_string(&self) -> String {
+        self.text.to_string()
+    }
+}
+
+/// escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// accept connections and answer /metrics, /healthz and /readyz
+pub async fn serve(listener: TcpListener, scrapes: mpsc::Sender<Scrape>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("metrics listening on: {}", addr);
+    }
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream, scrapes.clone()));
+            }
+            Err(e) => warn!("metrics accept error: {}", e),
+        }
+    }
+}
+
+/// read the request line and write the reply
+async fn respond(mut stream: TcpStream, scrapes: mpsc::Sender<Scrape>) {
+    let mut buf = vec![0; 2048];
+    let len = match timeout(SCRAPE_TIMEOUT, stream.read(&mut buf)).await {
+        Ok(Ok(len)) => len,
+        _ => return,
+    };
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (code, body) = match path {
+        "/healthz" => (200, "ok".to_string()),
+        "/readyz" | "/metrics" => match scrape(&scrapes).await {
+            Some(text) if path == "/metrics" => (200, text),
+            Some(_) => (200, "ready".to_string()),
+            None => (503, "not ready".to_string()),
+        },
+        _ => (404, "not found".to_string()),
+    };
+
+    if let Err(e) = stream
+        .write_all(http_response(code, &body).as_bytes())
+        .await
+    {
+        warn!("metrics write error: {}", e);
+    }
+}
+
+/// ask the server loop for the metrics; none if it doesn't answer in time
+async fn scrape(scrapes: &mpsc::Sender<Scrape>) -> Option<String> {
+    let (tx, rx) = oneshot::channel();
+    scrapes.send(tx).await.ok()?;
+    timeout(SCRAPE_TIMEOUT, rx).await.ok()?.ok()
+}
+
+/// format the http reply
+fn http_response(code: u16, body: &str) -> String {
+    let (reason, content_type) = match code {
+        200 => ("OK", "text/plain; version=0.0.4"),
+        404 => ("Not Found", "text/plain"),
+        _ => ("Service Unavailable", "text/plain"),
+    };
+
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition() {
+        let mut out = Exposition::default();
+        out.metric("udp_keys", "gauge", "the number of keys");
+        out.sample("udp_keys", &[("db", "default")], 10);
+        out.sample("udp_keys", &[("db", "a\"b")], 1);
+        out.sample("udp_up", &[], 1);
+
+        let text = out.as_string();
+        assert!(text.starts_with("# HELP udp_keys the number of keys\n# TYPE udp_keys gauge\n"));
+        assert!(text.contains("udp_keys{db=\"default\"} 10\n"));
+        assert!(text.contains("udp_keys{db=\"a\\\"b\"} 1\n"));
+        assert!(text.ends_with("udp_up 1\n"));
+    }
+
+    #[test]
+    fn response() {
+        let text = http_response(200, "ok");
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Length: 2\r\n"));
+        assert!(text.ends_with("\r\n\r\nok"));
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+        reply
+    }
+
+    #[tokio::test]
+    async fn endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::channel::<Scrape>(4);
+        tokio::spawn(serve(listener, tx));
+
+        // stands in for the server loop
+        tokio::spawn(async move {
+            while let Some(reply) = rx.recv().await {
+                let _ = reply.send("udp_up 1\n".to_string());
+            }
+        });
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/readyz").await.ends_with("ready"));
+        assert!(get(addr, "/metrics").await.ends_with("udp_up 1\n"));
+        assert!(get(addr, "/nope").await.starts_with("HTTP/1.1 404"));
+    }
+}
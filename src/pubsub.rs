@@ -0,0 +1,159 @@
+/// client subscriptions held by socket address with a lease that must be renewed
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// the default lease in seconds
+pub const DEFAULT_LEASE: u64 = 60;
+
+#[derive(Debug, Default, Clone)]
+pub struct Subscriptions {
+    topics: BTreeMap<String, HashMap<SocketAddr, u64>>,
+    lease: u64,
+}
+
+impl Subscriptions {
+    /// create the subscriptions with the lease in seconds
+    pub fn create(lease: u64) -> Subscriptions {
+        Subscriptions {
+            topics: BTreeMap::new(),
+            lease: lease.max(1),
+        }
+    }
+
+    /// the lease in seconds
+    pub fn lease(&self) -> u64 {
+        self.lease
+    }
+
+    /// add or renew the subscription and return the lease in seconds
+    pub fn subscribe(&mut self, topic: &str, addr: SocketAddr) -> u64 {
+        let expires = get_ts() + self.lease;
+        self.topics
+            .entry(topic.to_string())
+            .or_default()
+            .insert(addr, expires);
+
+        self.lease
+    }
+
+    /// remove the subscription; returns true if it existed
+    pub fn unsubscribe(&mut self, topic: &str, addr: SocketAddr) -> bool {
+        let removed = self
+            .topics
+            .get_mut(topic)
+            .is_some_and(|subs| subs.remove(&addr).is_some());
+        self.topics.retain(|_, subs| !subs.is_empty());
+
+        removed
+    }
+
+    /// the addresses subscribed to the topics that match
+    pub fn subscribers<F>(&mut self, matches: F) -> Vec<SocketAddr>
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.expire();
+        let mut list: Vec<SocketAddr> = self
+            .topics
+            .iter()
+            .filter(|(topic, _)| matches(topic))
+            .flat_map(|(_, subs)| subs.keys().copied())
+            .collect();
+        list.sort();
+        list.dedup();
+
+        list
+    }
+
+    /// the number of subscriptions for the address
+    pub fn count_for(&self, addr: SocketAddr) -> usize {
+        self.topics
+            .values()
+            .filter(|subs| subs.contains_key(&addr))
+            .count()
+    }
+
+    /// the total number of active subscriptions
+    pub fn len(&self) -> usize {
+        self.topics.values().map(|subs| subs.len()).sum()
+    }
+
+    /// true if there are no subscriptions
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// list the subscriptions as topic:addr:seconds-left
+    pub fn list(&self) -> Vec<String> {
+        let now = get_ts();
+        self.topics
+            .iter()
+            .flat_map(|(topic, subs)| {
+                subs.iter().map(move |(addr, expires)| {
+                    format!("{}:{}:{}", topic, addr, expires.saturating_sub(now))
+                })
+            })
+            .collect()
+    }
+
+    /// remove the expired subscriptions and return the number removed
+    pub fn expire(&mut self) -> usize {
+        let now = get_ts();
+        let mut removed = 0;
+        for subs in self.topics.values_mut() {
+            let before = subs.len();
+            subs.retain(|_, expires| *expires > now);
+            removed += before - subs.len();
+        }
+        self.topics.retain(|_, subs| !subs.is_empty());
+
+        removed
+    }
+}
+
+/// return the unix timestamp
+fn get_ts() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("seconds")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn subscribe_unsubscribe() {
+        let mut subs = Subscriptions::create(DEFAULT_LEASE);
+        assert_eq!(subs.subscribe("news", addr(9001)), DEFAULT_LEASE);
+        subs.subscribe("news", addr(9002));
+        subs.subscribe("sports", addr(9001));
+        assert_eq!(subs.len(), 3);
+        assert_eq!(subs.count_for(addr(9001)), 2);
+
+        let list = subs.subscribers(|topic| topic == "news");
+        assert_eq!(list, vec![addr(9001), addr(9002)]);
+
+        assert!(subs.unsubscribe("news", addr(9001)));
+        assert!(!subs.unsubscribe("news", addr(9001)));
+        assert_eq!(subs.subscribers(|topic| topic == "news"), vec![addr(9002)]);
+        assert_eq!(subs.list().len(), 2);
+    }
+
+    #[test]
+    fn expire() {
+        let mut subs = Subscriptions::create(DEFAULT_LEASE);
+        subs.subscribe("news", addr(9001));
+        subs.topics.get_mut("news").unwrap().insert(addr(9002), 0);
+
+        assert_eq!(subs.expire(), 1);
+        assert_eq!(subs.subscribers(|_| true), vec![addr(9001)]);
+        assert!(!subs.is_empty());
+    }
+}
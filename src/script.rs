@@ -0,0 +1,518 @@
+/// a small sandboxed expression language for the eval command
+///
+/// scripts are s-expressions, e.g. (if (== (get "a") "on") (set "b" "yes") "skipped").
+/// a form that is not a built-in runs the handler command with that name.  every form
+/// evaluated counts as a step and a script is stopped when it runs out of steps or time, or
+/// builds text longer than its max text.
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// the default max number of forms evaluated by a script
+pub const DEFAULT_MAX_STEPS: usize = 1000;
+
+/// the default max run time of a script in milliseconds
+pub const DEFAULT_TIMEOUT_MS: u64 = 50;
+
+/// the default max length in bytes of the text a script builds with concat
+pub const DEFAULT_MAX_TEXT: usize = 65536;
+
+/// the max nesting of forms
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Text(String),
+    Int(i64),
+    Symbol(String),
+    List(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Text(String),
+}
+
+impl Atom {
+    /// nil, false, zero and the empty string are false
+    pub fn is_true(&self) -> bool {
+        match self {
+            Atom::Nil => false,
+            Atom::Bool(b) => *b,
+            Atom::Int(n) => *n != 0,
+            Atom::Text(s) => !s.is_empty(),
+        }
+    }
+
+    /// the value as an integer, parsing text
+    fn as_int(&self) -> Result<i64, ScriptError> {
+        match self {
+            Atom::Int(n) => Ok(*n),
+            Atom::Text(s) => s
+                .parse::<i64>()
+                .map_err(|_| ScriptError::Syntax(format!("not a number: {}", s))),
+            _ => Err(ScriptError::Syntax(format!("not a number: {}", self))),
+        }
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Atom::Nil => write!(f, "nil"),
+            Atom::Bool(b) => write!(f, "{}", b),
+            Atom::Int(n) => write!(f, "{}", n),
+            Atom::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    /// the script could not be parsed or used a form incorrectly
+    Syntax(String),
+    /// the script ran out of steps or time, or built text that is too long
+    Limit(String),
+    /// a command failed; the caller holds the failing response
+    Command(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Syntax(msg) | ScriptError::Limit(msg) | ScriptError::Command(msg) => {
+                write!(f, "{}", msg)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_steps: usize,
+    pub timeout: Duration,
+    pub max_text: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: DEFAULT_MAX_STEPS,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            max_text: DEFAULT_MAX_TEXT,
+        }
+    }
+}
+
+/// parse and run the script, calling out for commands; returns the value of the last form
+pub fn run<F>(source: &str, limits: &Limits, call: F) -> Result<Atom, ScriptError>
+where
+    F: FnMut(&str, Vec<String>) -> Result<Atom, ScriptError>,
+{
+    let forms = parse(source)?;
+    let mut interpreter = Interpreter {
+        call,
+        vars: HashMap::new(),
+        steps: 0,
+        limits: *limits,
+        started: Instant::now(),
+    };
+
+    let mut result = Atom::Nil;
+    for form in forms.iter() {
+        result = interpreter.eval(form)?;
+    }
+
+    Ok(result)
+}
+
+/// split the source into parens, quoted strings and words
+fn tokenize(source: &str) -> Result<Vec<Expr>, ScriptError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' | ')' => tokens.push(Expr::Symbol(ch.to_string())),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some(c) => text.push(c),
+                            None => return Err(ScriptError::Syntax("unterminated string".into())),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(ScriptError::Syntax("unterminated string".into())),
+                    }
+                }
+                tokens.push(Expr::Text(text));
+            }
+            c if c.is_whitespace() => (),
+            _ => {
+                let mut word = ch.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '(' || *c == ')' || *c == '"' {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                match word.parse::<i64>() {
+                    Ok(n) => tokens.push(Expr::Int(n)),
+                    Err(_) => tokens.push(Expr::Symbol(word)),
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// parse the source into a list of top level forms
+fn parse(source: &str) -> Result<Vec<Expr>, ScriptError> {
+    let tokens = tokenize(source)?;
+    let mut pos = 0;
+    let mut forms = Vec::new();
+    while pos < tokens.len() {
+        forms.push(parse_form(&tokens, &mut pos, 0)?);
+    }
+
+    if forms.is_empty() {
+        return Err(ScriptError::Syntax("empty script".into()));
+    }
+
+    Ok(forms)
+}
+
+fn parse_form(tokens: &[Expr], pos: &mut usize, depth: usize) -> Result<Expr, ScriptError> {
+    if depth > MAX_DEPTH {
+        return Err(ScriptError::Syntax("forms nested too deep".into()));
+    }
+
+    let token = tokens[*pos].clone();
+    *pos += 1;
+    match token {
+        Expr::Symbol(s) if s == "(" => {
+            let mut list = Vec::new();
+            loop {
+                match tokens.get(*pos) {
+                    Some(Expr::Symbol(s)) if s == ")" => {
+                        *pos += 1;
+                        return Ok(Expr::List(list));
+                    }
+                    Some(_) => list.push(parse_form(tokens, pos, depth + 1)?),
+                    None => return Err(ScriptError::Syntax("missing )".into())),
+                }
+            }
+        }
+        Expr::Symbol(s) if s == ")" => Err(ScriptError::Syntax("unexpected )".into())),
+        token => Ok(token),
+    }
+}
+
+struct Interpreter<F> {
+    call: F,
+    vars: HashMap<String, Atom>,
+    steps: usize,
+    limits: Limits,
+    started: Instant,
+}
+
+impl<F> Interpreter<F>
+where
+    F: FnMut(&str, Vec<String>) -> Result<Atom, ScriptError>,
+{
+    /// count the step and stop the script if it is over its limits
+    fn step(&mut self) -> Result<(), ScriptError> {
+        self.steps += 1;
+        if self.steps > self.limits.max_steps {
+            return Err(ScriptError::Limit(format!(
+                "step limit {}",
+                self.limits.max_steps
+            )));
+        }
+
+        if self.started.elapsed() > self.limits.timeout {
+            return Err(ScriptError::Limit(format!(
+                "time limit {}ms",
+                self.limits.timeout.as_millis()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Atom, ScriptError> {
+        self.step()?;
+        match expr {
+            Expr::Text(s) => Ok(Atom::Text(s.to_string())),
+            Expr::Int(n) => Ok(Atom::Int(*n)),
+            Expr::Symbol(name) => match name.as_str() {
+                "nil" => Ok(Atom::Nil),
+                "true" => Ok(Atom::Bool(true)),
+                "false" => Ok(Atom::Bool(false)),
+                _ => self
+                    .vars
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| ScriptError::Syntax(format!("unknown variable {}", name))),
+            },
+            Expr::List(list) => match list.split_first() {
+                Some((Expr::Symbol(name), args)) => self.apply(name, args),
+                _ => Err(ScriptError::Syntax("a form must start with a name".into())),
+            },
+        }
+    }
+
+    /// evaluate the built-in form or run the command
+    fn apply(&mut self, name: &str, args: &[Expr]) -> Result<Atom, ScriptError> {
+        match name {
+            "do" => {
+                let mut result = Atom::Nil;
+                for arg in args {
+                    result = self.eval(arg)?;
+                }
+                Ok(result)
+            }
+            "let" => match args {
+                [Expr::Symbol(var), value] => {
+                    let value = self.eval(value)?;
+                    self.vars.insert(var.to_string(), value.clone());
+                    Ok(value)
+                }
+                _ => Err(ScriptError::Syntax("usage: (let name value)".into())),
+            },
+            "if" => {
+                if !(2..=3).contains(&args.len()) {
+                    return Err(ScriptError::Syntax("usage: (if cond then [else])".into()));
+                }
+                if self.eval(&args[0])?.is_true() {
+                    self.eval(&args[1])
+                } else {
+                    args.get(2)
+                        .map_or(Ok(Atom::Nil), |otherwise| self.eval(otherwise))
+                }
+            }
+            "while" => {
+                let Some((cond, body)) = args.split_first() else {
+                    return Err(ScriptError::Syntax("usage: (while cond body...)".into()));
+                };
+                let mut result = Atom::Nil;
+                while self.eval(cond)?.is_true() {
+                    for form in body {
+                        result = self.eval(form)?;
+                    }
+                }
+                Ok(result)
+            }
+            "and" => {
+                let mut result = Atom::Bool(true);
+                for arg in args {
+                    result = self.eval(arg)?;
+                    if !result.is_true() {
+                        break;
+                    }
+                }
+                Ok(result)
+            }
+            "or" => {
+                let mut result = Atom::Bool(false);
+                for arg in args {
+                    result = self.eval(arg)?;
+                    if result.is_true() {
+                        break;
+                    }
+                }
+                Ok(result)
+            }
+            "not" => {
+                let values = self.eval_args(name, args, 1)?;
+                Ok(Atom::Bool(!values[0].is_true()))
+            }
+            "nil?" => {
+                let values = self.eval_args(name, args, 1)?;
+                Ok(Atom::Bool(values[0] == Atom::Nil))
+            }
+            "==" | "!=" => {
+                let values = self.eval_args(name, args, 2)?;
+                let equal = match (values[0].as_int(), values[1].as_int()) {
+                    (Ok(a), Ok(b)) => a == b,
+                    _ => values[0].to_string() == values[1].to_string(),
+                };
+                Ok(Atom::Bool(equal == (name == "==")))
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let values = self.eval_args(name, args, 2)?;
+                let (a, b) = (values[0].as_int()?, values[1].as_int()?);
+                let result = match name {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                };
+                Ok(Atom::Bool(result))
+            }
+            "+" | "-" | "*" => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval(arg)?.as_int()?);
+                }
+                let Some((first, rest)) = values.split_first() else {
+                    return Err(ScriptError::Syntax(format!("{} needs a number", name)));
+                };
+                let mut total = *first;
+                for n in rest {
+                    let next = match name {
+                        "+" => total.checked_add(*n),
+                        "-" => total.checked_sub(*n),
+                        _ => total.checked_mul(*n),
+                    };
+                    total = next.ok_or_else(|| ScriptError::Syntax("number overflow".into()))?;
+                }
+                Ok(Atom::Int(total))
+            }
+            "concat" => {
+                let mut text = String::new();
+                for arg in args {
+                    text.push_str(&self.eval(arg)?.to_string());
+                    if text.len() > self.limits.max_text {
+                        return Err(ScriptError::Limit(format!(
+                            "text limit {}",
+                            self.limits.max_text
+                        )));
+                    }
+                }
+                Ok(Atom::Text(text))
+            }
+            _ => {
+                let mut params = Vec::with_capacity(args.len());
+                for arg in args {
+                    params.push(self.eval(arg)?.to_string());
+                }
+                (self.call)(name, params)
+            }
+        }
+    }
+
+    /// evaluate exactly count args
+    fn eval_args(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        count: usize,
+    ) -> Result<Vec<Atom>, ScriptError> {
+        if args.len() != count {
+            return Err(ScriptError::Syntax(format!(
+                "{} takes {} argument(s)",
+                name, count
+            )));
+        }
+
+        let mut values = Vec::with_capacity(count);
+        for arg in args {
+            values.push(self.eval(arg)?);
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// run the script against a map standing in for the handler's get and set
+    fn run_with(source: &str, store: &mut HashMap<String, String>) -> Result<Atom, ScriptError> {
+        run(source, &Limits::default(), |cmd, args| match cmd {
+            "get" => Ok(store.get(&args[0]).cloned().map_or(Atom::Nil, Atom::Text)),
+            "set" => {
+                store.insert(args[0].to_string(), args[1].to_string());
+                Ok(Atom::Text("ok".into()))
+            }
+            _ => Err(ScriptError::Command(cmd.to_string())),
+        })
+    }
+
+    #[test]
+    fn conditional_set() {
+        let mut store = HashMap::new();
+        store.insert("a".to_string(), "on".to_string());
+
+        let script = r#"(if (== (get "a") "on") (set "b" "yes") "skipped")"#;
+        assert_eq!(run_with(script, &mut store), Ok(Atom::Text("ok".into())));
+        assert_eq!(store.get("b").unwrap(), "yes");
+
+        let script = r#"(if (nil? (get "c")) "missing")"#;
+        assert_eq!(
+            run_with(script, &mut store),
+            Ok(Atom::Text("missing".into()))
+        );
+    }
+
+    #[test]
+    fn variables_and_numbers() {
+        let mut store = HashMap::new();
+        store.insert("count".to_string(), "41".to_string());
+
+        let script = r#"(let n (+ (get "count") 1)) (set "count" n) (concat "n=" n)"#;
+        assert_eq!(run_with(script, &mut store), Ok(Atom::Text("n=42".into())));
+        assert_eq!(store.get("count").unwrap(), "42");
+
+        let script = "(let i 0) (while (< i 5) (let i (+ i 1))) (and (>= i 5) (not false))";
+        assert_eq!(run_with(script, &mut store), Ok(Atom::Bool(true)));
+        assert!(run_with("(+ 9223372036854775807 1)", &mut store).is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let mut store = HashMap::new();
+        let result = run_with("(while true 1)", &mut store);
+        assert_eq!(result, Err(ScriptError::Limit("step limit 1000".into())));
+
+        let limits = Limits {
+            max_steps: usize::MAX,
+            timeout: Duration::from_millis(5),
+            ..Limits::default()
+        };
+        let result = run("(while true 1)", &limits, |_, _| Ok(Atom::Nil));
+        assert!(matches!(result, Err(ScriptError::Limit(_))));
+
+        let result = run_with(
+            r#"(let s "x") (while true (let s (concat s s)))"#,
+            &mut store,
+        );
+        assert_eq!(result, Err(ScriptError::Limit("text limit 65536".into())));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut store = HashMap::new();
+        for script in [
+            "",
+            "(get \"a\"",
+            ")",
+            "(\"a\")",
+            "(let 1 2)",
+            "x",
+            "(not 1 2)",
+        ] {
+            let result = run_with(script, &mut store);
+            assert!(matches!(result, Err(ScriptError::Syntax(_))), "{}", script);
+        }
+
+        let deep = format!("{}1{}", "(do ".repeat(40), ")".repeat(40));
+        assert!(matches!(
+            run_with(&deep, &mut store),
+            Err(ScriptError::Syntax(_))
+        ));
+        assert!(matches!(
+            run_with("(flushdb)", &mut store),
+            Err(ScriptError::Command(_))
+        ));
+    }
+}
@@ -2,62 +2,261 @@
 //
 //
 
+use crate::access::{AccessEntry, AccessLog};
 use crate::config::Config;
 use crate::handler::{Handler, Request, Response, Status};
+use crate::metrics::{self, Exposition, Scrape};
+use crate::trace;
 use anyhow::Result;
-use log::info;
-use tokio::net::UdpSocket;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::Instrument;
 
 #[derive(Debug, Default, Clone)]
 pub struct Server {
     config: Config,
     handler: Handler,
+    bytes_in: u64,
+    bytes_out: u64,
+    dropped: u64,
+    requests: u64,
+    /// shared by clones of the server, which write to the same file
+    access: Option<Arc<Mutex<AccessLog>>>,
 }
 
 impl Server {
     /// create the server from config and handler
     pub fn create(config: Config, handler: Handler) -> Server {
-        Server { config, handler }
+        Server {
+            config,
+            handler,
+            ..Server::default()
+        }
+    }
+
+    /// start the metrics listener when an address is configured
+    async fn start_metrics(&self, scrapes: mpsc::Sender<Scrape>) -> Result<()> {
+        if let Some(addr) = &self.config.metrics_address {
+            let listener = TcpListener::bind(addr).await?;
+            tokio::spawn(metrics::serve(listener, scrapes));
+        }
+
+        Ok(())
+    }
+
+    /// open the access log when it is configured
+    fn open_access_log(&mut self) -> Result<()> {
+        if let Some(config) = &self.config.access_log {
+            info!("access log: {}", config.path);
+            self.access = Some(Arc::new(Mutex::new(AccessLog::create(config)?)));
+        }
+
+        Ok(())
+    }
+
+    /// write the request to the access log; the auth key is not logged
+    fn log_access(
+        &mut self,
+        addr: SocketAddr,
+        msg: &str,
+        code: u16,
+        bytes_in: usize,
+        bytes_out: usize,
+    ) {
+        let Some(access) = &self.access else {
+            return;
+        };
+
+        let mut parts = msg.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+        let key = parts.next().filter(|_| command != "auth");
+        let entry = AccessEntry {
+            client: Some(addr),
+            command: command.to_string(),
+            key: key.map(|key| key.to_string()),
+            code,
+            bytes_in,
+            bytes_out,
+            mutating: Handler::is_mutating(command),
+        };
+
+        let mut access = access.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = access.record(&entry) {
+            warn!("could not write the access log: {}", e);
+        }
+    }
+
+    /// re-read the config file and apply the settings that can change while running
+    fn reload_config(&mut self) {
+        match self.handler.reload_config() {
+            Ok((_, restart)) if !restart.is_empty() => {
+                warn!("restart the server to change: {}", restart.join(", "))
+            }
+            Ok(_) => (),
+            Err(e) => warn!("config not reloaded: {}", e),
+        }
+    }
+
+    /// render the server and handler metrics
+    fn metrics(&self) -> String {
+        let mut out = Exposition::default();
+        out.metric("udp_received_bytes_total", "counter", "bytes received");
+        out.sample("udp_received_bytes_total", &[], self.bytes_in);
+        out.metric("udp_sent_bytes_total", "counter", "bytes sent");
+        out.sample("udp_sent_bytes_total", &[], self.bytes_out);
+        out.metric(
+            "udp_dropped_datagrams_total",
+            "counter",
+            "datagrams truncated on receive or that could not be sent",
+        );
+        out.sample("udp_dropped_datagrams_total", &[], self.dropped);
+        self.handler.write_metrics(&mut out);
+
+        out.as_string()
     }
 
     async fn bind_socket(&self) -> Result<UdpSocket> {
-        let addr = format!("{}:{}", self.config.host, self.config.port);
+        let addr = self.config.socket_address()?;
         info!("listening on: {}", addr);
         let sock = UdpSocket::bind(addr).await?;
 
         Ok(sock)
     }
 
-    /// pull out the handler
-    pub async fn start(&mut self) -> Result<()> {
-        let sock = self.bind_socket().await.expect("open socket error");
-
-        loop {
-            // listen for a message
-            let mut buf = [0; 128];
-
-            let (len, addr) = sock.recv_from(&mut buf).await?;
-            let msg = String::from_utf8_lossy(&buf[..len]);
-            let msg = msg.trim();
+    /// receive, dispatch and answer one datagram inside the request span; true when it is the
+    /// shutdown command
+    async fn handle_datagram(
+        &mut self,
+        sock: &UdpSocket,
+        data: &[u8],
+        addr: SocketAddr,
+    ) -> Result<bool> {
+        let log_values = self.handler.log_values();
+        let msg = String::from_utf8_lossy(data);
+        let msg = msg.trim();
 
-            info!("recv: {} bytes from {:?}, msg: {}", len, addr, msg);
+        {
+            let _receive = tracing::info_span!("receive").entered();
+            self.bytes_in += data.len() as u64;
+            info!(
+                "recv: {} bytes from {:?}, msg: {}",
+                data.len(),
+                addr,
+                trace::redact(msg, log_values)
+            );
 
             if msg == "shutdown" {
                 info!("{}", "received shutdown command");
                 Config::remove_pid_file();
-                break;
+                return Ok(true);
             }
+        }
 
-            // split this into [cmd, param, param]
+        // split this into [cmd, param, param]
+        let started = Instant::now();
+        let response = {
+            let _dispatch = tracing::info_span!("dispatch").entered();
             let response = match Request::from_message(msg) {
-                Ok(request) => self.handler.handle_request(request),
+                Ok(request) => self.handler.handle_client_request(addr, request),
                 Err(e) => Response::create(Status::bad_request(), e.to_string()),
             };
+            self.handler.record_slow(Some(addr), msg, started.elapsed());
+            response
+        };
 
-            // return the response
+        // return the response, then push any messages queued for subscribers
+        async {
             let resp = response.as_string();
             let len = sock.send_to(resp.as_bytes(), addr).await?;
-            info!("returned: {:?}, size {}.", response, len);
+            self.bytes_out += len as u64;
+            self.log_access(addr, msg, response.status.code, data.len(), len);
+            if log_values {
+                info!("returned: {:?}, size {}.", response, len);
+            } else {
+                info!("returned: {}, size {}.", response.status.code, len);
+            }
+
+            for (to, message) in self.handler.drain_messages() {
+                match sock.send_to(message.as_bytes(), to).await {
+                    Ok(len) => self.bytes_out += len as u64,
+                    Err(e) => {
+                        warn!("could not push to {}: {}", to, e);
+                        self.dropped += 1;
+                    }
+                }
+            }
+
+            Ok::<(), anyhow::Error>(())
+        }
+        .instrument(tracing::info_span!("send"))
+        .await?;
+
+        let cmd = msg.split_whitespace().next().unwrap_or_default();
+        trace::finish(cmd, response.status.code, started.elapsed());
+
+        Ok(false)
+    }
+
+    /// pull out the handler
+    pub async fn start(&mut self) -> Result<()> {
+        let sock = self.bind_socket().await?;
+        // one byte over the max payload shows a datagram that was too big and truncated
+        let max_payload = self.config.max_payload();
+        let mut buf = vec![0; max_payload + 1];
+        let (scrapes, mut scrape_requests) = mpsc::channel::<Scrape>(8);
+        self.start_metrics(scrapes).await?;
+        self.open_access_log()?;
+        let mut hangups = signal(SignalKind::hangup())?;
+        let mut last_snapshot = Instant::now();
+
+        loop {
+            // the interval may change with config set or a reload, so it is read each time
+            let interval = self.handler.snapshot_interval();
+            let next_snapshot = last_snapshot + interval.unwrap_or_default();
+
+            // listen for a message, or answer a metrics scrape, reload or take the snapshots
+            // between messages
+            let (len, addr) = tokio::select! {
+                Some(reply) = scrape_requests.recv() => {
+                    let _ = reply.send(self.metrics());
+                    continue;
+                }
+                Some(_) = hangups.recv() => {
+                    info!("received SIGHUP, reloading the config");
+                    self.reload_config();
+                    continue;
+                }
+                _ = tokio::time::sleep_until(next_snapshot.into()), if interval.is_some() => {
+                    self.handler.save_snapshots();
+                    last_snapshot = Instant::now();
+                    continue;
+                }
+                received = sock.recv_from(&mut buf) => received?,
+            };
+
+            self.requests += 1;
+            if len > max_payload {
+                warn!(
+                    "datagram from {} is over {} bytes and was truncated",
+                    addr, max_payload
+                );
+                self.dropped += 1;
+            }
+            let len = len.min(max_payload);
+
+            let span = tracing::info_span!("request", request_id = self.requests, client = %addr);
+            if self
+                .handle_datagram(&sock, &buf[..len], addr)
+                .instrument(span)
+                .await?
+            {
+                break;
+            }
         }
 
         Ok(())
@@ -67,7 +266,10 @@ impl Server {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{AccessLogConfig, DEFAULT_MAX_PAYLOAD};
     use tiny_kv::db::DataStore;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
     // use tokio_test::*;
 
     fn create_config() -> Config {
@@ -94,6 +296,35 @@ mod tests {
             port: 9898,
             logging_config: ctx.logging_config.to_string(),
             data_file: ctx.data_file.clone(),
+            snapshots: ctx.snapshots.clone(),
+            databases: ctx.databases.clone(),
+            admin_keys: ctx.admin_keys.clone(),
+            max_payload: ctx.max_payload,
+            pubsub_lease: ctx.pubsub_lease,
+            max_watchers: ctx.max_watchers,
+            max_subscribers: ctx.max_subscribers,
+            key_history: ctx.key_history,
+            script_max_steps: ctx.script_max_steps,
+            script_timeout_ms: ctx.script_timeout_ms,
+            max_memory: ctx.max_memory,
+            eviction_policy: ctx.eviction_policy.clone(),
+            max_key_length: ctx.max_key_length,
+            max_value_length: ctx.max_value_length,
+            max_keys: ctx.max_keys,
+            config_file: ctx.config_file.clone(),
+            overrides: ctx.overrides.clone(),
+            metrics_address: Some("127.0.0.1:9899".to_string()),
+            slowlog_threshold_us: ctx.slowlog_threshold_us,
+            slowlog_max_len: ctx.slowlog_max_len,
+            log_values: ctx.log_values,
+            log_level: ctx.log_level.clone(),
+            access_log: Some(AccessLogConfig {
+                path: "tests/access-server-out/access.log".to_string(),
+                max_size: None,
+                keep: None,
+                audit: Some(true),
+            }),
+            unknown_keys: vec![],
         };
 
         let handler = Handler::new(create_db());
@@ -124,6 +355,31 @@ mod tests {
             let result = client.recv_from(&mut buf).await;
             println!("{:?}", result);
 
+            // a datagram of exactly the max payload is not truncated
+            let msg = format!("get {}", "k".repeat(DEFAULT_MAX_PAYLOAD - 4));
+            let result = client.send_to(msg.as_bytes(), addr.clone()).await;
+            println!("{:?}", result);
+
+            let mut buf = [0; 2048];
+            let result = client.recv_from(&mut buf).await;
+            println!("{:?}", result);
+
+            // scrape the metrics between requests
+            let mut stream = TcpStream::connect("127.0.0.1:9899").await.unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+                .await
+                .unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            assert!(reply.contains("udp_requests_total{command=\"ping\",code=\"200\"} 1"));
+            assert!(reply.contains("udp_received_bytes_total 1037"));
+            assert!(reply.contains("udp_dropped_datagrams_total 0"));
+
+            let text = std::fs::read_to_string("tests/access-server-out/access.log").unwrap();
+            assert!(text.contains(" ping - 200 4 "));
+            assert!(text.contains(" set flarb 400 9 "));
+
             // now send the shutdown
             let result = client.send_to(b"shutdown", addr.clone()).await;
             println!("{:?}", result);
@@ -131,6 +387,7 @@ mod tests {
 
         client_task.await.unwrap();
         server_task.await.unwrap();
+        std::fs::remove_dir_all("tests/access-server-out").unwrap();
     }
 
     #[tokio::test]
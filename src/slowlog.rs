@@ -0,0 +1,147 @@
+/// a bounded log of the requests that took longer than the threshold to handle
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// the default threshold in microseconds
+pub const DEFAULT_THRESHOLD_US: u64 = 10_000;
+
+/// the default number of entries kept
+pub const DEFAULT_MAX_LEN: usize = 128;
+
+/// the params are cut to this many characters
+const MAX_PARAMS_LEN: usize = 64;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct SlowEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub client: String,
+    pub command: String,
+    pub params: String,
+    pub duration_us: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SlowLog {
+    entries: VecDeque<SlowEntry>,
+    threshold: Duration,
+    max_len: usize,
+    next_id: u64,
+}
+
+impl SlowLog {
+    /// create the log with the threshold in microseconds and the number of entries to keep
+    pub fn create(threshold_us: u64, max_len: usize) -> SlowLog {
+        SlowLog {
+            entries: VecDeque::new(),
+            threshold: Duration::from_micros(threshold_us),
+            max_len,
+            next_id: 0,
+        }
+    }
+
+    /// change the threshold and the number of entries kept, dropping the oldest that don't fit
+    pub fn configure(&mut self, threshold_us: u64, max_len: usize) {
+        self.threshold = Duration::from_micros(threshold_us);
+        self.max_len = max_len;
+        self.entries.truncate(max_len);
+    }
+
+    /// record the request if it took longer than the threshold; returns true if recorded
+    pub fn record(&mut self, client: Option<SocketAddr>, msg: &str, elapsed: Duration) -> bool {
+        if elapsed < self.threshold || self.max_len == 0 {
+            return false;
+        }
+
+        let (command, rest) = msg.split_once(' ').unwrap_or((msg, ""));
+        let mut params: String = rest.chars().take(MAX_PARAMS_LEN).collect();
+        if params.len() < rest.len() {
+            params.push_str("...");
+        }
+
+        self.next_id += 1;
+        self.entries.push_front(SlowEntry {
+            id: self.next_id,
+            timestamp: get_ts(),
+            client: client.map_or("local".to_string(), |addr| addr.to_string()),
+            command: command.to_string(),
+            params,
+            duration_us: elapsed.as_micros() as u64,
+        });
+        self.entries.truncate(self.max_len);
+
+        true
+    }
+
+    /// the last n entries, newest first
+    pub fn get(&self, n: usize) -> Vec<&SlowEntry> {
+        self.entries.iter().take(n).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// remove all of the entries
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// return the unix timestamp
+fn get_ts() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("seconds")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_and_ring() {
+        let mut log = SlowLog::create(1000, 2);
+        assert!(!log.record(None, "get k1", Duration::from_micros(999)));
+        assert!(log.is_empty());
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 9001));
+        assert!(log.record(Some(addr), "keys", Duration::from_millis(5)));
+        assert!(log.record(None, "savedb", Duration::from_millis(7)));
+        assert!(log.record(None, "loaddb data/users.kv", Duration::from_millis(9)));
+        assert_eq!(log.len(), 2);
+
+        let entries = log.get(10);
+        assert_eq!(entries[0].id, 3);
+        assert_eq!(entries[0].command, "loaddb");
+        assert_eq!(entries[0].params, "data/users.kv");
+        assert_eq!(entries[0].duration_us, 9000);
+        assert_eq!(entries[1].client, "local");
+        assert_eq!(log.get(1).len(), 1);
+
+        log.configure(1000, 1);
+        assert_eq!(log.get(10)[0].id, 3);
+        assert_eq!(log.len(), 1);
+
+        log.reset();
+        assert_eq!(log.len(), 0);
+    }
+
+    #[test]
+    fn params_truncated() {
+        let mut log = SlowLog::create(0, 10);
+        let msg = format!("set k1 {}", "é".repeat(100));
+        log.record(None, &msg, Duration::from_millis(1));
+
+        let params = &log.get(1)[0].params;
+        assert_eq!(params.chars().count(), MAX_PARAMS_LEN + 3);
+        assert!(params.ends_with("..."));
+    }
+}
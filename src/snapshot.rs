@@ -0,0 +1,245 @@
+/// versioned snapshots of the data store with a retention policy
+use crate::config::SnapshotConfig;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_kv::db::DataStore;
+
+const PREFIX: &str = "snapshot-";
+const EXTENSION: &str = ".kv";
+
+#[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
+pub struct Snapshot {
+    pub version: u64,
+    pub path: PathBuf,
+    pub modified: u64,
+}
+
+impl Snapshot {
+    /// return the snapshot as version:timestamp
+    pub fn as_string(&self) -> String {
+        format!("{}:{}", self.version, self.modified)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Snapshots {
+    folder: String,
+    keep: Option<usize>,
+    max_age: Option<u64>,
+}
+
+impl Snapshots {
+    /// create the snapshot manager from config
+    pub fn create(config: &SnapshotConfig) -> Snapshots {
+        Snapshots {
+            folder: config.folder.to_string(),
+            keep: config.keep,
+            max_age: config.max_age,
+        }
+    }
+
+    /// the snapshots for a named database are kept in a sub-folder
+    pub fn for_database(&self, name: &str, default: &str) -> Snapshots {
+        let mut snapshots = self.clone();
+        if name != default {
+            snapshots.folder = Path::new(&self.folder)
+                .join(name)
+                .to_str()
+                .unwrap_or_default()
+                .to_string();
+        }
+
+        snapshots
+    }
+
+    /// the full path for a given version
+    pub fn path_for(&self, version: u64) -> PathBuf {
+        Path::new(&self.folder).join(format!("{}{:06}{}", PREFIX, version, EXTENSION))
+    }
+
+    /// return all snapshots in the folder sorted by version, oldest first
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        let folder = Path::new(&self.folder);
+        if !folder.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut list = Vec::new();
+        for entry in fs::read_dir(folder)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .to_string();
+
+            let version = name
+                .strip_prefix(PREFIX)
+                .and_then(|n| n.strip_suffix(EXTENSION))
+                .and_then(|n| n.parse::<u64>().ok());
+
+            if let Some(version) = version {
+                let modified = fs::metadata(&path)?
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                list.push(Snapshot {
+                    version,
+                    path,
+                    modified,
+                });
+            }
+        }
+
+        list.sort_by_key(|s| s.version);
+
+        Ok(list)
+    }
+
+    /// write the data store to the next version then apply the retention policy
+    pub fn save(&self, db: &DataStore) -> Result<Snapshot> {
+        fs::create_dir_all(&self.folder)?;
+        let version = self.list()?.last().map_or(1, |s| s.version + 1);
+        let path = self.path_for(version);
+        let filename = path.to_str().unwrap_or_default().to_string();
+
+        let sz = db.savedb(&filename)?;
+        info!(
+            "saved snapshot {}, {} elements to {}",
+            version, sz, filename
+        );
+
+        let removed = self.rotate()?;
+        if removed > 0 {
+            info!("removed {} expired snapshots", removed);
+        }
+
+        Ok(Snapshot {
+            version,
+            path,
+            modified: get_ts(),
+        })
+    }
+
+    /// load the snapshot version into a new data store
+    pub fn restore(&self, version: u64) -> Result<DataStore> {
+        let path = self.path_for(version);
+        if !path.exists() {
+            return Err(anyhow!("snapshot version {} not found", version));
+        }
+
+        let db = DataStore::create();
+        let filename = path.to_str().unwrap_or_default();
+        let sz = db.loaddb(filename)?;
+        info!("restored snapshot {}, {} elements", version, sz);
+
+        Ok(db)
+    }
+
+    /// remove snapshots beyond the keep count or older than max age; the newest is never removed
+    pub fn rotate(&self) -> Result<usize> {
+        let list = self.list()?;
+        if list.len() < 2 {
+            return Ok(0);
+        }
+
+        let now = get_ts();
+        let newest = list.len() - 1;
+        let mut removed = 0;
+        for (idx, snapshot) in list.iter().enumerate() {
+            if idx == newest {
+                break;
+            }
+
+            let over_count = self.keep.is_some_and(|keep| newest - idx >= keep.max(1));
+            let too_old = self
+                .max_age
+                .is_some_and(|age| now.saturating_sub(snapshot.modified) > age);
+
+            if over_count || too_old {
+                match fs::remove_file(&snapshot.path) {
+                    Ok(_) => removed += 1,
+                    Err(e) => warn!("could not remove snapshot {:?}: {}", snapshot.path, e),
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// return the unix timestamp
+fn get_ts() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("seconds")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_snapshots(folder: &str, keep: Option<usize>) -> Snapshots {
+        let _ = fs::remove_dir_all(folder);
+        Snapshots::create(&SnapshotConfig {
+            folder: folder.to_string(),
+            keep,
+            max_age: None,
+            interval: None,
+        })
+    }
+
+    #[test]
+    fn save_list_restore() {
+        let snapshots = create_snapshots("tests/snapshots-save-out", None);
+        let db = DataStore::create();
+        db.set("k1", b"first".to_vec());
+
+        let snapshot = snapshots.save(&db).unwrap();
+        assert_eq!(snapshot.version, 1);
+
+        db.set("k1", b"second".to_vec());
+        db.set("k2", b"other".to_vec());
+        let snapshot = snapshots.save(&db).unwrap();
+        assert_eq!(snapshot.version, 2);
+
+        let list = snapshots.list().unwrap();
+        assert_eq!(list.len(), 2);
+
+        let restored = snapshots.restore(1).unwrap();
+        assert_eq!(restored.dbsize(), 1);
+        assert_eq!(restored.get("k1").unwrap(), b"first".to_vec());
+
+        assert!(snapshots.restore(99).is_err());
+        let _ = fs::remove_dir_all("tests/snapshots-save-out");
+    }
+
+    #[test]
+    fn rotate_keep() {
+        let snapshots = create_snapshots("tests/snapshots-rotate-out", Some(2));
+        let db = DataStore::create();
+        for _ in 0..4 {
+            snapshots.save(&db).unwrap();
+        }
+
+        let versions: Vec<u64> = snapshots
+            .list()
+            .unwrap()
+            .iter()
+            .map(|s| s.version)
+            .collect();
+        assert_eq!(versions, vec![3, 4]);
+        let _ = fs::remove_dir_all("tests/snapshots-rotate-out");
+    }
+
+    #[test]
+    fn list_missing_folder() {
+        let snapshots = create_snapshots("tests/snapshots-missing-out", None);
+        assert!(snapshots.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+/// per-command counters and latency histograms recorded around dispatch
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// the number of latency buckets; bucket n holds latencies up to 2^n microseconds
+const BUCKETS: usize = 25;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum_micros: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum_micros: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// add the latency to its bucket
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().max(1) as u64;
+        let bucket = (u64::BITS - (micros - 1).leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum_micros += micros;
+    }
+
+    /// the number of latencies recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// the total of the latencies recorded in microseconds
+    pub fn sum_micros(&self) -> u64 {
+        self.sum_micros
+    }
+
+    /// the upper bound in microseconds of each bucket and the cumulative count up to it
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        let mut total = 0;
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(n, count)| {
+                total += count;
+                (1 << n, total)
+            })
+            .collect()
+    }
+
+    /// the upper bound in microseconds of the bucket that holds the percentile, e.g. 0.99
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let rank = ((self.count as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (n, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return 1 << n;
+            }
+        }
+
+        1 << (BUCKETS - 1)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub not_found: u64,
+    /// the calls by response code
+    pub codes: BTreeMap<u16, u64>,
+    pub latency: Histogram,
+}
+
+/// the counters and latency percentiles for a command
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub calls: u64,
+    pub errors: u64,
+    pub not_found: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    commands: BTreeMap<String, CommandStats>,
+}
+
+impl Stats {
+    /// record a call to the command with its response code and latency
+    pub fn record(&mut self, cmd: &str, code: u16, elapsed: Duration) {
+        let stats = self.commands.entry(cmd.to_string()).or_default();
+        stats.calls += 1;
+        match code {
+            404 => stats.not_found += 1,
+            code if code >= 400 => stats.errors += 1,
+            _ => (),
+        }
+        *stats.codes.entry(code).or_default() += 1;
+        stats.latency.record(elapsed);
+    }
+
+    /// the calls for each command
+    pub fn calls(&self) -> BTreeMap<&str, u64> {
+        self.commands
+            .iter()
+            .map(|(cmd, stats)| (cmd.as_str(), stats.calls))
+            .collect()
+    }
+
+    /// the counters and percentiles for the command
+    pub fn summary(&self, cmd: &str) -> Option<Summary> {
+        self.commands.get(cmd).map(|stats| Summary {
+            calls: stats.calls,
+            errors: stats.errors,
+            not_found: stats.not_found,
+            p50_us: stats.latency.percentile(0.50),
+            p95_us: stats.latency.percentile(0.95),
+            p99_us: stats.latency.percentile(0.99),
+        })
+    }
+
+    /// the stats for each command that has been called, in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &CommandStats)> {
+        self.commands
+            .iter()
+            .map(|(cmd, stats)| (cmd.as_str(), stats))
+    }
+
+    /// the commands that have been called, in order
+    pub fn commands(&self) -> Vec<&str> {
+        self.commands.keys().map(|cmd| cmd.as_str()).collect()
+    }
+
+    /// clear all of the counters
+    pub fn reset(&mut self) {
+        self.commands.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_percentiles() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(0.5), 0);
+
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(3));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(10));
+        }
+
+        assert_eq!(histogram.percentile(0.50), 4);
+        assert_eq!(histogram.percentile(0.90), 4);
+        assert_eq!(histogram.percentile(0.95), 16384);
+        assert_eq!(histogram.percentile(0.99), 16384);
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.sum_micros(), 90 * 3 + 10 * 10_000);
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[2], (4, 90));
+        assert_eq!(buckets[BUCKETS - 1], (1 << (BUCKETS - 1), 100));
+
+        histogram.record(Duration::from_secs(3600));
+        assert_eq!(histogram.percentile(1.0), 1 << (BUCKETS - 1));
+    }
+
+    #[test]
+    fn counters() {
+        let mut stats = Stats::default();
+        stats.record("get", 200, Duration::from_micros(5));
+        stats.record("get", 404, Duration::from_micros(5));
+        stats.record("get", 409, Duration::from_micros(5));
+        stats.record("keys", 200, Duration::from_micros(900));
+
+        let summary = stats.summary("get").unwrap();
+        assert_eq!(summary.calls, 3);
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.not_found, 1);
+        assert_eq!(summary.p99_us, 8);
+        assert_eq!(stats.commands(), vec!["get", "keys"]);
+        assert_eq!(stats.calls()["keys"], 1);
+        let (_, get) = stats.iter().next().unwrap();
+        assert_eq!(get.codes[&404], 1);
+
+        stats.reset();
+        assert!(stats.summary("get").is_none());
+    }
+}
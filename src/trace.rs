@@ -0,0 +1,336 @@
+/// request spans and fields for structured logs, and redaction of logged values
+///
+/// requests are traced with `tracing` spans: a request span with the request id and client, and
+/// receive, dispatch and send spans inside it.  the LogSubscriber writes the spans and events to
+/// log4rs: the fields of the entered spans are kept in the log mdc, so a json encoder (see
+/// config/json.yaml) writes them on every line logged while the request is handled; a pattern
+/// encoder can show them with {X(name)}.  the mdc is thread local, so the server loop runs on
+/// the main thread rather than a spawned task.
+use log::debug;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_log::AsLog;
+
+/// what replaces the params when values are not logged
+pub const REDACTED: &str = "(redacted)";
+
+thread_local! {
+    /// the ids of the spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug)]
+struct SpanData {
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    started: Instant,
+    refs: usize,
+}
+
+/// collects the fields of a span or event as text
+#[derive(Debug, Default)]
+struct Fields(Vec<(&'static str, String)>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+/// a tracing subscriber that writes to the log, e.g. log4rs, with the span fields in the mdc
+#[derive(Debug, Default)]
+pub struct LogSubscriber {
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+impl LogSubscriber {
+    /// make the subscriber the global default; false if there already is one
+    pub fn install() -> bool {
+        tracing::subscriber::set_global_default(LogSubscriber::default()).is_ok()
+    }
+
+    /// put the fields of the entered spans in the mdc, with the innermost name as `span`
+    fn update_mdc(&self, removed: &[(&'static str, String)]) {
+        for (name, _) in removed {
+            log_mdc::remove(*name);
+        }
+        log_mdc::remove("span");
+
+        let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        ENTERED.with(|entered| {
+            for id in entered.borrow().iter() {
+                if let Some(span) = spans.get(id) {
+                    log_mdc::insert("span", span.name);
+                    for (name, value) in &span.fields {
+                        log_mdc::insert(*name, value.to_string());
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Subscriber for LogSubscriber {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.is_span() || log::logger().enabled(&metadata.as_log())
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let span = SpanData {
+            name: attrs.metadata().name(),
+            fields: fields.0,
+            started: Instant::now(),
+            refs: 1,
+        };
+        self.spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, span);
+
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = self
+            .spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&span.into_u64())
+        {
+            span.fields.extend(fields.0);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    /// log the message with the event's other fields in the mdc for the one line
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let (message, fields): (Vec<_>, Vec<_>) = fields
+            .0
+            .into_iter()
+            .partition(|(name, _)| *name == "message");
+        let message = message
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<String>();
+
+        for (name, value) in &fields {
+            log_mdc::insert(*name, value.to_string());
+        }
+        let metadata = event.metadata();
+        log::logger().log(
+            &log::Record::builder()
+                .metadata(metadata.as_log())
+                .args(format_args!("{}", message))
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .build(),
+        );
+        self.update_mdc(&fields);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+        self.update_mdc(&[]);
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(pos) = entered.iter().rposition(|entered| *entered == id) {
+                entered.remove(pos);
+            }
+        });
+
+        let fields = self
+            .spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .map(|span| span.fields.clone())
+            .unwrap_or_default();
+        self.update_mdc(&fields);
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self
+            .spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&span.into_u64())
+        {
+            span.refs += 1;
+        }
+
+        span.clone()
+    }
+
+    /// drop the span when its last handle closes and log how long it was open
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+
+        if let Some(data) = spans.remove(&span.into_u64()) {
+            drop(spans);
+            debug!(
+                "{} took {}us",
+                data.name,
+                data.started.elapsed().as_micros()
+            );
+        }
+
+        true
+    }
+}
+
+/// log the request line with its command, status code and latency as fields
+pub fn finish(command: &str, code: u16, elapsed: Duration) {
+    let latency_us = elapsed.as_micros() as u64;
+    tracing::info!(
+        command,
+        code,
+        latency_us,
+        "request {} {} {}us",
+        command,
+        code,
+        latency_us
+    );
+}
+
+/// the message to log; unless values are logged, the values of the command are replaced with
+/// REDACTED, e.g. everything after the key, every param of auth and eval, and the values of
+/// each command inside exec
+pub fn redact(msg: &str, log_values: bool) -> String {
+    if log_values {
+        return msg.to_string();
+    }
+
+    let (cmd, rest) = msg.split_once(' ').unwrap_or((msg, ""));
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return msg.to_string();
+    }
+
+    // the command may have a db: prefix
+    match cmd.rsplit(':').next().unwrap_or(cmd) {
+        "auth" | "eval" => format!("{} {}", cmd, REDACTED),
+        "exec" => match serde_json::from_str::<Vec<Vec<String>>>(rest) {
+            Ok(commands) => {
+                let commands: Vec<Vec<String>> = commands.iter().map(|c| redact_words(c)).collect();
+                format!(
+                    "{} {}",
+                    cmd,
+                    serde_json::to_string(&commands).unwrap_or_default()
+                )
+            }
+            Err(_) => format!("{} {}", cmd, REDACTED),
+        },
+        "config" => match rest.splitn(3, ' ').collect::<Vec<_>>()[..] {
+            [action, name, _] => format!("{} {} {} {}", cmd, action, name, REDACTED),
+            _ => msg.to_string(),
+        },
+        _ => match rest.split_once(' ') {
+            Some((key, _)) => format!("{} {} {}", cmd, key, REDACTED),
+            None => msg.to_string(),
+        },
+    }
+}
+
+/// redact an exec command that is already split into words, keeping the command and key
+fn redact_words(words: &[String]) -> Vec<String> {
+    let Some(cmd) = words.first() else {
+        return vec![];
+    };
+
+    match cmd.as_str() {
+        "ifversion" => words.to_vec(),
+        "auth" | "eval" => vec![cmd.to_string(), REDACTED.to_string()],
+        _ if words.len() > 2 => vec![cmd.to_string(), words[1].to_string(), REDACTED.to_string()],
+        _ => words.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted() {
+        assert_eq!(redact("set k1 secret value", false), "set k1 (redacted)");
+        assert_eq!(redact("set k1 secret", true), "set k1 secret");
+        assert_eq!(redact("auth secret-key", false), "auth (redacted)");
+        assert_eq!(redact("get k1", false), "get k1");
+        assert_eq!(redact("dbsize", false), "dbsize");
+        assert_eq!(
+            redact("config:set k1 secret", false),
+            "config:set k1 (redacted)"
+        );
+    }
+
+    #[test]
+    fn redacted_by_command() {
+        assert_eq!(
+            redact(r#"exec [["set","k","secret"],["get","k"]]"#, false),
+            r#"exec [["set","k","(redacted)"],["get","k"]]"#
+        );
+        assert_eq!(redact("exec [not json secret", false), "exec (redacted)");
+        assert_eq!(
+            redact(r#"eval (set "k" "secret")"#, false),
+            "eval (redacted)"
+        );
+        assert_eq!(
+            redact("config set admin_keys secret", false),
+            "config set admin_keys (redacted)"
+        );
+        assert_eq!(redact("config get port", false), "config get port");
+    }
+
+    #[test]
+    fn spans_and_fields() {
+        let field = |name: &str| log_mdc::get(name, |v| v.map(String::from));
+        tracing::subscriber::with_default(LogSubscriber::default(), || {
+            let request = tracing::info_span!("request", request_id = 7u64).entered();
+            {
+                let _receive = tracing::info_span!("receive").entered();
+                assert_eq!(field("span"), Some("receive".into()));
+                assert_eq!(field("request_id"), Some("7".into()));
+            }
+            assert_eq!(field("span"), Some("request".into()));
+
+            finish("get", 200, Duration::from_micros(12));
+            assert_eq!(field("command"), None);
+            drop(request);
+            assert_eq!(field("span"), None);
+            assert_eq!(field("request_id"), None);
+        });
+    }
+}
@@ -0,0 +1,194 @@
+/// typed values stored alongside plain strings in the data store
+///
+/// typed values are kept in the data store as a tag and json, e.g. @hash:{"name":"john"}
+/// so they round-trip through savedb/loaddb and snapshots like any other value.
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+const HASH_TAG: &str = "@hash:";
+const LIST_TAG: &str = "@list:";
+const SET_TAG: &str = "@set:";
+const ZSET_TAG: &str = "@zset:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Hash(BTreeMap<String, String>),
+    List(VecDeque<String>),
+    Set(BTreeSet<String>),
+    ZSet(BTreeMap<String, f64>),
+}
+
+impl Value {
+    /// decode the stored bytes; anything without a valid tag is plain text
+    pub fn decode(bytes: Vec<u8>) -> Value {
+        let text = String::from_utf8_lossy(&bytes).to_string();
+        if let Some(json) = text.strip_prefix(HASH_TAG) {
+            if let Ok(map) = serde_json::from_str(json) {
+                return Value::Hash(map);
+            }
+        } else if let Some(json) = text.strip_prefix(LIST_TAG) {
+            if let Ok(list) = serde_json::from_str(json) {
+                return Value::List(list);
+            }
+        } else if let Some(json) = text.strip_prefix(SET_TAG) {
+            if let Ok(set) = serde_json::from_str(json) {
+                return Value::Set(set);
+            }
+        } else if let Some(json) = text.strip_prefix(ZSET_TAG) {
+            if let Ok(zset) = serde_json::from_str(json) {
+                return Value::ZSet(zset);
+            }
+        }
+
+        Value::Text(text)
+    }
+
+    /// encode the value to store
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::Text(text) => text.as_bytes().to_vec(),
+            Value::Hash(map) => {
+                let json = serde_json::to_string(map).unwrap_or_default();
+                format!("{}{}", HASH_TAG, json).into_bytes()
+            }
+            Value::List(list) => {
+                let json = serde_json::to_string(list).unwrap_or_default();
+                format!("{}{}", LIST_TAG, json).into_bytes()
+            }
+            Value::Set(set) => {
+                let json = serde_json::to_string(set).unwrap_or_default();
+                format!("{}{}", SET_TAG, json).into_bytes()
+            }
+            Value::ZSet(zset) => {
+                let json = serde_json::to_string(zset).unwrap_or_default();
+                format!("{}{}", ZSET_TAG, json).into_bytes()
+            }
+        }
+    }
+
+    /// true if the plain value would be read back as a typed value
+    pub fn is_typed(bytes: &[u8]) -> bool {
+        !matches!(Value::decode(bytes.to_vec()), Value::Text(_))
+    }
+
+    /// the name of the value type
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Text(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+        }
+    }
+
+    /// true for a typed value with no elements; these are removed from the store
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::Text(_) => false,
+            Value::Hash(map) => map.is_empty(),
+            Value::List(list) => list.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::ZSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+/// return the sorted set members ordered by score, then member
+pub fn by_score(zset: &BTreeMap<String, f64>) -> Vec<String> {
+    let mut members: Vec<(&String, &f64)> = zset.iter().collect();
+    members.sort_by(|a, b| a.1.total_cmp(b.1).then(a.0.cmp(b.0)));
+
+    members.into_iter().map(|(m, _)| m.to_string()).collect()
+}
+
+/// convert the inclusive start/stop indexes, negative from the end, to a range within len
+pub fn index_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+
+    if len == 0 || start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_round_trip() {
+        let mut map = BTreeMap::new();
+        map.insert("first_name".to_string(), "john".to_string());
+        map.insert("last_name".to_string(), "smith jr".to_string());
+        let value = Value::Hash(map);
+
+        let bytes = value.encode();
+        assert!(Value::is_typed(&bytes));
+        assert_eq!(Value::decode(bytes), value);
+        assert_eq!(value.type_name(), "hash");
+    }
+
+    #[test]
+    fn list_round_trip() {
+        let list: VecDeque<String> = vec!["job-1".to_string(), "job 2".to_string()].into();
+        let value = Value::List(list);
+
+        let bytes = value.encode();
+        assert!(Value::is_typed(&bytes));
+        assert_eq!(Value::decode(bytes), value);
+        assert_eq!(value.type_name(), "list");
+    }
+
+    #[test]
+    fn set_zset_round_trip() {
+        let set: BTreeSet<String> = ["red", "blue"].iter().map(|s| s.to_string()).collect();
+        let value = Value::Set(set);
+        assert_eq!(Value::decode(value.encode()), value);
+        assert_eq!(value.type_name(), "set");
+
+        let mut zset = BTreeMap::new();
+        zset.insert("alice".to_string(), 12.5);
+        zset.insert("bob".to_string(), 3.0);
+        let value = Value::ZSet(zset.clone());
+        assert_eq!(Value::decode(value.encode()), value);
+        assert_eq!(value.type_name(), "zset");
+
+        zset.insert("carol".to_string(), 3.0);
+        assert_eq!(by_score(&zset), vec!["bob", "carol", "alice"]);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(index_range(0, -1, 5), Some((0, 4)));
+        assert_eq!(index_range(1, 2, 5), Some((1, 2)));
+        assert_eq!(index_range(-2, -1, 5), Some((3, 4)));
+        assert_eq!(index_range(0, 100, 5), Some((0, 4)));
+        assert_eq!(index_range(-100, 1, 5), Some((0, 1)));
+        assert_eq!(index_range(3, 1, 5), None);
+        assert_eq!(index_range(5, 10, 5), None);
+        assert_eq!(index_range(0, -1, 0), None);
+    }
+
+    #[test]
+    fn text_values() {
+        let value = Value::decode(b"first_name: john, last_name: smith".to_vec());
+        assert_eq!(value.type_name(), "string");
+        assert!(!Value::is_typed(b"plain value"));
+
+        // a tag without valid json is plain text
+        let value = Value::decode(b"@hash:not json".to_vec());
+        assert_eq!(value, Value::Text("@hash:not json".to_string()));
+    }
+}